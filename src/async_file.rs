@@ -0,0 +1,173 @@
+use std::path::Path;
+
+use futures::stream::StreamExt;
+
+use crate::{
+    engine::Engine,
+    errors::{FileProcessingError, InputError},
+    input_types::TransactionReaderBuilder,
+};
+
+impl Engine {
+    /// Async counterpart of processing a CSV file with
+    /// `deserialize_transactions`, for services running on tokio. Rows are
+    /// parsed and applied one at a time as the file is read, without blocking
+    /// the runtime. Rows which fail to parse are skipped, as in the CLI, and
+    /// rejected transactions are handled as in `process`.
+    pub async fn process_file_async<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(), FileProcessingError> {
+        self.process_file_async_with(path, TransactionReaderBuilder::new())
+            .await
+    }
+
+    /// Same as `process_file_async`, reading the file as configured by
+    /// `reader`: type encoding, headers and strict types. Transactions carry
+    /// the line they were read from. With strict types, processing stops at
+    /// the first row with an unknown `type`, failing with
+    /// `FileProcessingError::Input`.
+    pub async fn process_file_async_with<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        reader: TransactionReaderBuilder,
+    ) -> Result<(), FileProcessingError> {
+        let file = tokio::fs::File::open(path).await?;
+        let mut csv = csv_async::AsyncReaderBuilder::new()
+            .trim(csv_async::Trim::All)
+            .has_headers(reader.has_headers)
+            .flexible(!reader.has_headers)
+            .create_reader(file);
+        let headers = if reader.has_headers {
+            Some(csv.headers().await?.iter().collect::<csv::StringRecord>())
+        } else {
+            None
+        };
+        let mut records = csv.into_records();
+        while let Some(record) = records.next().await {
+            let record = match record {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            let line = record.position().map_or(0, csv_async::Position::line);
+            let mut position = csv::Position::new();
+            position.set_line(line);
+            let mut record: csv::StringRecord = record.iter().collect();
+            record.set_position(Some(position));
+            match reader.parse_record(&record, headers.as_ref(), line) {
+                Ok(transaction) => self.process(Some(transaction))?,
+                Err(err @ InputError::UnknownType { .. }) if reader.strict_types => {
+                    return Err(err.into())
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::{
+        ids::{ClientId, TxId},
+        input_types::{deserialize_transactions, TypeEncoding},
+    };
+
+    fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "toy-payments-engine-async-{}-{}.csv",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn should_match_sync_processing() {
+        let path = temp_file(
+            "sync",
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.5\n\
+             deposit, 2, 2, 2.0\n\
+             withdrawal, 1, 3, 0.5\n\
+             dispute, 2, 2,\n\
+             not a row\n",
+        );
+
+        let mut engine = Engine::new();
+        engine.process_file_async(&path).await.unwrap();
+
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(&path)
+            .unwrap();
+        let mut sync = Engine::new();
+        sync.process(deserialize_transactions(reader, TypeEncoding::Name).filter_map(Result::ok))
+            .unwrap();
+        assert_eq!(engine.client_list(), sync.client_list());
+        assert_eq!(engine.client_list().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_read_type_codes_with_lines() {
+        let path = temp_file(
+            "codes",
+            "type, client, tx, amount\n\
+             1, 1, 1, 1.5\n\
+             2, 1, 2, 0.5\n",
+        );
+
+        let mut engine = Engine::new().with_audit(true);
+        engine
+            .process_file_async_with(
+                &path,
+                TransactionReaderBuilder::new().type_encoding(TypeEncoding::Code),
+            )
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let client = engine.client(ClientId(1)).unwrap();
+        assert_eq!(client.available, Decimal::new(1, 0));
+        assert_eq!(client.entry(TxId(1)).unwrap().line, Some(2));
+        assert_eq!(client.entry(TxId(2)).unwrap().line, Some(3));
+    }
+
+    #[tokio::test]
+    async fn should_stop_at_unknown_type_when_strict() {
+        let path = temp_file(
+            "strict",
+            "deposit, 1, 1, 1.5\n\
+             transfer, 1, 2, 1.0\n\
+             deposit, 1, 3, 1.0\n",
+        );
+
+        let mut engine = Engine::new();
+        let result = engine
+            .process_file_async_with(
+                &path,
+                TransactionReaderBuilder::new()
+                    .has_headers(false)
+                    .strict_types(true),
+            )
+            .await;
+        std::fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(FileProcessingError::Input(InputError::UnknownType { line, value })) => {
+                assert_eq!(line, 2);
+                assert_eq!(value, "transfer");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(
+            engine.client(ClientId(1)).unwrap().available,
+            Decimal::new(15, 1)
+        );
+    }
+}
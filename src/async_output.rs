@@ -0,0 +1,64 @@
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    engine::Engine,
+    errors::OutputError,
+    output::{write_header, write_row, STREAMING_FLUSH_EVERY},
+};
+
+impl Engine {
+    /// Async counterpart of `write_clients_streaming`, e.g. for streaming
+    /// the final state over a socket. Clients are serialized one at a time
+    /// and the writer is flushed periodically and at the end.
+    pub async fn write_clients_async<W: AsyncWrite + Unpin>(
+        &self,
+        w: &mut W,
+    ) -> Result<(), OutputError> {
+        let mut buffer = Vec::new();
+        write_header(&mut buffer)?;
+        w.write_all(&buffer).await?;
+        for (written, (id, client)) in self.iter_clients().enumerate() {
+            buffer.clear();
+            write_row(&mut buffer, u32::from(id.0), &client.snapshot())?;
+            w.write_all(&buffer).await?;
+            if (written + 1) % STREAMING_FLUSH_EVERY == 0 {
+                w.flush().await?;
+            }
+        }
+        w.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::{
+        input_types::{Transaction, TransactionType},
+        output::write_clients_streaming,
+    };
+
+    #[tokio::test]
+    async fn should_match_sync_writer() {
+        let mut engine = Engine::new();
+        engine
+            .process(vec![
+                Transaction::new(TransactionType::Deposit, 2, 1, Some(Decimal::new(15, 1))),
+                Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(2, 0))),
+                Transaction::new(TransactionType::Dispute, 1, 2, None),
+            ])
+            .unwrap();
+
+        let mut output = Vec::new();
+        engine.write_clients_async(&mut output).await.unwrap();
+
+        let mut expected = Vec::new();
+        write_clients_streaming(engine.iter_clients(), &mut expected).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+}
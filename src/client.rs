@@ -3,27 +3,64 @@ use std::collections::HashMap;
 use rust_decimal::Decimal;
 
 use crate::{
-    errors::TransactionProcessingError,
+    errors::{CloseAccountError, CloseBlocker, InvariantViolation, TransactionProcessingError},
+    ids::TxId,
     input_types::{Transaction, TransactionType},
 };
 
 #[derive(Clone, Debug, PartialEq)]
-enum BalanceChangeEntryType {
+pub enum BalanceChangeEntryType {
     Deposit,
     Withdrawal,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-enum BalanceChangeEntryStatus {
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BalanceChangeEntryStatus {
+    /// Authorized deposit awaiting capture.
+    Pending,
     Valid,
     ActiveDispute,
     ChargedBack,
 }
+/// Handling of disputes on withdrawals, i.e. claims that the client didn't
+/// make the withdrawal and wants the funds back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WithdrawalDisputePolicy {
+    /// Reject with `DisputeOnWithdrawal`.
+    Reject,
+    /// Move the withdrawn amount to held funds until the dispute ends. A
+    /// chargeback returns it to available funds, a resolve drops it.
+    Held,
+    /// Credit the withdrawn amount to available funds right away. A resolve
+    /// takes it back, even if that leaves available funds negative.
+    Available,
+}
+
+impl Default for WithdrawalDisputePolicy {
+    fn default() -> Self {
+        WithdrawalDisputePolicy::Held
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
-struct BalanceChangeEntry {
+pub struct BalanceChangeEntry {
     pub ty: BalanceChangeEntryType,
     pub amount: Decimal,
     pub status: BalanceChangeEntryStatus,
+    /// Timestamp of the dispute transaction while under active dispute.
+    pub disputed_at: Option<u64>,
+    /// Group of the deposit or withdrawal, see `Transaction::group`.
+    pub group: Option<u32>,
+    /// Ordinal of the deposit or withdrawal among transactions applied to the
+    /// client, starting from 1.
+    pub sequence: u64,
+    /// Memo of the deposit or withdrawal, see `Transaction::memo`.
+    pub memo: Option<String>,
+    /// Policy a withdrawal was disputed under, while under active dispute.
+    pub withdrawal_dispute_policy: Option<WithdrawalDisputePolicy>,
+    /// Input line of the deposit or withdrawal, recorded only when the engine
+    /// runs with `Engine::with_audit`.
+    pub line: Option<u64>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -31,110 +68,696 @@ pub struct Client {
     balance_changes: HashMap<u32, BalanceChangeEntry>,
     pub available: Decimal,
     pub held: Decimal,
+    /// Authorized deposits awaiting capture. Not part of `total`.
+    pub pending: Decimal,
+    pub is_frozen: bool,
+    /// Set by `close`, after which all transactions are rejected.
+    pub is_closed: bool,
+    /// Number of transactions successfully applied to the client.
+    processed: u64,
+    /// Highest `held` ever reached, see `held_high_water_mark`.
+    held_high_water_mark: Decimal,
+    /// Entries under active dispute, kept up to date so that snapshots don't
+    /// have to scan the history.
+    active_disputes: usize,
+}
+
+/// Bits of `Client::status_flags`.
+pub const STATUS_FROZEN: u8 = 0b001;
+pub const STATUS_ACTIVE_DISPUTES: u8 = 0b010;
+pub const STATUS_CHARGEBACKS: u8 = 0b100;
+
+/// Balances and status of a client, without its transaction history.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientSnapshot {
+    pub available: Decimal,
+    pub held: Decimal,
+    /// Authorized deposits awaiting capture. Not part of `total`.
+    pub pending: Decimal,
     pub is_frozen: bool,
+    /// Deposits and withdrawals under active dispute.
+    pub disputed_count: usize,
+}
+
+impl ClientSnapshot {
+    pub fn total(&self) -> Decimal {
+        self.available + self.held
+    }
 }
 
 impl Client {
+    /// Creates a client with given balances and no transaction history.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `held` is negative.
+    pub fn with_balances(available: Decimal, held: Decimal, is_frozen: bool) -> Client {
+        assert!(
+            !held.is_sign_negative(),
+            "held balance can't be negative: {}",
+            held
+        );
+        Client {
+            balance_changes: HashMap::new(),
+            available,
+            held,
+            pending: Decimal::new(0, 0),
+            is_frozen,
+            is_closed: false,
+            processed: 0,
+            held_high_water_mark: held,
+            active_disputes: 0,
+        }
+    }
+
     pub fn total(&self) -> Decimal {
         self.available + self.held
     }
-    pub fn process_transaction(&mut self, transaction: Transaction) {
-        let result = match transaction.ty {
+
+    /// Highest amount of held funds the client ever had, e.g. to show peak
+    /// exposure after disputes were resolved.
+    pub fn held_high_water_mark(&self) -> Decimal {
+        self.held_high_water_mark
+    }
+
+    /// Fraction of the total balance which is held, or `None` when the total
+    /// is zero.
+    pub fn held_ratio(&self) -> Option<Decimal> {
+        self.held.checked_div(self.total())
+    }
+
+    /// Rough approximation of the memory used by the client: its own size,
+    /// the allocated capacity of the history map (one control byte per slot)
+    /// and memos. Allocator overhead isn't accounted for.
+    pub fn estimated_bytes(&self) -> usize {
+        let slot = std::mem::size_of::<(u32, BalanceChangeEntry)>() + 1;
+        let memos: usize = self
+            .balance_changes
+            .values()
+            .filter_map(|entry| entry.memo.as_ref())
+            .map(String::capacity)
+            .sum();
+        std::mem::size_of::<Client>() + self.balance_changes.capacity() * slot + memos
+    }
+
+    /// Projection of `available` assuming every active dispute on a deposit
+    /// is resolved in the client's favour, i.e. with the disputed amounts
+    /// added back. Not a guarantee: a chargeback removes them for good.
+    pub fn optimistic_available(&self) -> Decimal {
+        self.available
+            + self
+                .balance_changes
+                .values()
+                .filter(|entry| entry.status == BalanceChangeEntryStatus::ActiveDispute)
+                .filter(|entry| entry.ty == BalanceChangeEntryType::Deposit)
+                .map(|entry| entry.amount)
+                .sum::<Decimal>()
+    }
+
+    pub fn snapshot(&self) -> ClientSnapshot {
+        ClientSnapshot {
+            available: self.available,
+            held: self.held,
+            pending: self.pending,
+            is_frozen: self.is_frozen,
+            disputed_count: self.active_disputes,
+        }
+    }
+
+    pub fn has_deposits(&self) -> bool {
+        self.balance_changes
+            .values()
+            .any(|entry| entry.ty == BalanceChangeEntryType::Deposit)
+    }
+
+    /// Deposit or withdrawal with the given tx id.
+    pub fn entry(&self, tx: TxId) -> Option<&BalanceChangeEntry> {
+        self.balance_changes.get(&tx.0)
+    }
+
+    pub(crate) fn record_line(&mut self, tx: TxId, line: u64) {
+        if let Some(entry) = self.balance_changes.get_mut(&tx.0) {
+            entry.line = Some(line);
+        }
+    }
+
+    /// Deposits and withdrawals of the client in no particular order, for
+    /// aggregates which don't need the ordering of `history`.
+    pub fn entries(&self) -> impl Iterator<Item = &BalanceChangeEntry> {
+        self.balance_changes.values()
+    }
+
+    /// Deposits and withdrawals of the client with their current status,
+    /// ordered by tx id.
+    pub fn history(&self) -> Vec<(u32, &BalanceChangeEntry)> {
+        let mut history: Vec<(u32, &BalanceChangeEntry)> = self
+            .balance_changes
+            .iter()
+            .map(|(tx, entry)| (*tx, entry))
+            .collect();
+        history.sort_unstable_by_key(|(tx, _)| *tx);
+        history
+    }
+
+    /// Number of charged back deposits and withdrawals.
+    pub fn chargebacks(&self) -> usize {
+        self.balance_changes
+            .values()
+            .filter(|entry| entry.status == BalanceChangeEntryStatus::ChargedBack)
+            .count()
+    }
+
+    /// Compact representation of the client status, see `STATUS_*` bits.
+    pub fn status_flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.is_frozen {
+            flags |= STATUS_FROZEN;
+        }
+        for entry in self.balance_changes.values() {
+            match entry.status {
+                BalanceChangeEntryStatus::Pending | BalanceChangeEntryStatus::Valid => {}
+                BalanceChangeEntryStatus::ActiveDispute => flags |= STATUS_ACTIVE_DISPUTES,
+                BalanceChangeEntryStatus::ChargedBack => flags |= STATUS_CHARGEBACKS,
+            }
+        }
+        flags
+    }
+
+    /// Whether the transaction is currently under an active dispute.
+    pub fn is_disputed(&self, tx: TxId) -> bool {
+        self.balance_changes.get(&tx.0).map_or(false, |entry| {
+            entry.status == BalanceChangeEntryStatus::ActiveDispute
+        })
+    }
+
+    /// Ids of transactions disputed before `cutoff` (Unix timestamp, seconds),
+    /// in ascending order. Disputes without a timestamp are never reported.
+    pub fn disputes_opened_before(&self, cutoff: u64) -> Vec<u32> {
+        let mut txs: Vec<u32> = self
+            .balance_changes
+            .iter()
+            .filter(|(_, entry)| entry.status == BalanceChangeEntryStatus::ActiveDispute)
+            .filter(|(_, entry)| entry.disputed_at.map_or(false, |at| at < cutoff))
+            .map(|(tx, _)| *tx)
+            .collect();
+        txs.sort_unstable();
+        txs
+    }
+
+    /// Ids of undisputed deposits tagged with `group`, in ascending order.
+    pub fn deposits_in_group(&self, group: u32) -> Vec<u32> {
+        let mut txs: Vec<u32> = self
+            .balance_changes
+            .iter()
+            .filter(|(_, entry)| entry.ty == BalanceChangeEntryType::Deposit)
+            .filter(|(_, entry)| entry.status == BalanceChangeEntryStatus::Valid)
+            .filter(|(_, entry)| entry.group == Some(group))
+            .map(|(tx, _)| *tx)
+            .collect();
+        txs.sort_unstable();
+        txs
+    }
+
+    /// Pairs of deposits with identical amounts made at most `window`
+    /// transactions of the client apart, earlier deposit first, ordered by
+    /// the earlier one. Amounts are compared by value, so `1.0` equals `1`.
+    pub fn duplicate_deposit_amounts(&self, window: u64) -> Vec<(TxId, TxId)> {
+        let mut deposits: Vec<(u64, u32, Decimal)> = self
+            .balance_changes
+            .iter()
+            .filter(|(_, entry)| entry.ty == BalanceChangeEntryType::Deposit)
+            .map(|(tx, entry)| (entry.sequence, *tx, entry.amount))
+            .collect();
+        deposits.sort_unstable();
+        let mut pairs = Vec::new();
+        for (i, (sequence, tx, amount)) in deposits.iter().enumerate() {
+            for (later_sequence, later_tx, later_amount) in &deposits[i + 1..] {
+                if later_sequence - sequence > window {
+                    break;
+                }
+                if later_amount == amount {
+                    pairs.push((TxId(*tx), TxId(*later_tx)));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Sum of all actively disputed deposits and withdrawals. Equals `held`
+    /// while only deposits are disputed, otherwise it's a gross figure.
+    pub fn disputed_amount(&self) -> Decimal {
+        self.balance_changes
+            .values()
+            .filter(|entry| entry.status == BalanceChangeEntryStatus::ActiveDispute)
+            .map(|entry| entry.amount)
+            .sum()
+    }
+
+    /// Tx id and amount of the largest currently disputed entry. Ties are
+    /// resolved in favour of the lower tx id.
+    pub fn max_active_dispute(&self) -> Option<(u32, Decimal)> {
+        self.balance_changes
+            .iter()
+            .filter(|(_, entry)| entry.status == BalanceChangeEntryStatus::ActiveDispute)
+            .map(|(tx, entry)| (*tx, entry.amount))
+            .max_by(|(tx_a, amount_a), (tx_b, amount_b)| {
+                amount_a.cmp(amount_b).then(tx_b.cmp(tx_a))
+            })
+    }
+
+    /// Available and held funds implied by the transaction history alone,
+    /// replayed in tx id order from a zero balance according to the current
+    /// status of each deposit and withdrawal. Fails with `BalanceOverflow` or
+    /// `BalanceUnderflow` if the replay leaves `Decimal` range.
+    pub fn expected_balances(&self) -> Result<(Decimal, Decimal), TransactionProcessingError> {
+        let mut available = Decimal::new(0, 0);
+        let mut held = Decimal::new(0, 0);
+        for (_, entry) in self.history() {
+            match (&entry.ty, &entry.status) {
+                (BalanceChangeEntryType::Deposit, BalanceChangeEntryStatus::Valid) => {
+                    available = checked_add(available, entry.amount)?
+                }
+                (BalanceChangeEntryType::Deposit, BalanceChangeEntryStatus::ActiveDispute) => {
+                    held = checked_add(held, entry.amount)?
+                }
+                (BalanceChangeEntryType::Withdrawal, BalanceChangeEntryStatus::Valid) => {
+                    available = checked_sub(available, entry.amount)?
+                }
+                (BalanceChangeEntryType::Withdrawal, BalanceChangeEntryStatus::ActiveDispute) => {
+                    if entry.withdrawal_dispute_policy == Some(WithdrawalDisputePolicy::Held) {
+                        available = checked_sub(available, entry.amount)?;
+                        held = checked_add(held, entry.amount)?;
+                    }
+                }
+                (_, BalanceChangeEntryStatus::Pending)
+                | (_, BalanceChangeEntryStatus::ChargedBack) => {}
+            }
+        }
+        Ok((available, held))
+    }
+
+    /// Checks that `held` equals the sum of actively disputed deposits and
+    /// withdrawals disputed under `WithdrawalDisputePolicy::Held`.
+    pub fn verify_held_consistency(&self) -> Result<(), InvariantViolation> {
+        let expected: Decimal = self
+            .balance_changes
+            .values()
+            .filter(|entry| entry.status == BalanceChangeEntryStatus::ActiveDispute)
+            .filter(|entry| {
+                entry.ty == BalanceChangeEntryType::Deposit
+                    || entry.withdrawal_dispute_policy == Some(WithdrawalDisputePolicy::Held)
+            })
+            .map(|entry| entry.amount)
+            .sum();
+        if expected != self.held {
+            return Err(InvariantViolation::HeldMismatch {
+                expected,
+                actual: self.held,
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies the transaction, rejecting disputes on withdrawals.
+    pub fn process_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        self.process_transaction_with_policy(transaction, WithdrawalDisputePolicy::Reject)
+    }
+
+    /// Applies the transaction, handling disputes on withdrawals according
+    /// to `withdrawal_disputes`.
+    pub fn process_transaction_with_policy(
+        &mut self,
+        transaction: &Transaction,
+        withdrawal_disputes: WithdrawalDisputePolicy,
+    ) -> Result<(), TransactionProcessingError> {
+        if self.is_closed {
+            return Err(TransactionProcessingError::AccountClosed);
+        }
+        let disputes_withdrawal = withdrawal_disputes != WithdrawalDisputePolicy::Reject
+            && self
+                .balance_changes
+                .get(&transaction.tx)
+                .map_or(false, |entry| {
+                    entry.ty == BalanceChangeEntryType::Withdrawal
+                });
+        match transaction.ty {
             TransactionType::Deposit => self.process_deposit(transaction),
             TransactionType::Withdrawal => self.process_withdrawal(transaction),
+            TransactionType::Dispute if disputes_withdrawal => {
+                self.process_withdrawal_dispute(transaction, withdrawal_disputes)
+            }
             TransactionType::Dispute => self.process_dispute(transaction),
             TransactionType::Resolve => self.process_resolve(transaction),
             TransactionType::Chargeback => self.process_chargeback(transaction),
-        };
-        if let Err(_err) = result {
-            // ignoring partner/client errors
+            TransactionType::Authorize => self.process_authorize(transaction),
+            TransactionType::Capture => self.process_capture(transaction),
+        }?;
+        self.processed += 1;
+        Ok(())
+    }
+
+    /// Closes the account, rejecting all further transactions with
+    /// `AccountClosed`. Requires an unfrozen account without active disputes,
+    /// held or pending funds; otherwise all blockers are returned. Available funds
+    /// are left as they are, for the payout to be recorded elsewhere.
+    pub fn close(&mut self) -> Result<(), CloseAccountError> {
+        let mut blockers = Vec::new();
+        if self.is_frozen {
+            blockers.push(CloseBlocker::Frozen);
+        }
+        blockers.extend(
+            self.history()
+                .into_iter()
+                .filter(|(_, entry)| entry.status == BalanceChangeEntryStatus::ActiveDispute)
+                .map(|(tx, _)| CloseBlocker::ActiveDispute { tx }),
+        );
+        if !self.held.is_zero() {
+            blockers.push(CloseBlocker::HeldFunds(self.held));
+        }
+        if !self.pending.is_zero() {
+            blockers.push(CloseBlocker::PendingFunds(self.pending));
         }
+        if !blockers.is_empty() {
+            return Err(CloseAccountError { blockers });
+        }
+        self.is_closed = true;
+        Ok(())
+    }
+
+    /// Number of transactions applied to the client after the given deposit
+    /// or withdrawal, `None` if the tx is unknown.
+    pub fn transactions_since(&self, tx: TxId) -> Option<u64> {
+        self.balance_changes
+            .get(&tx.0)
+            .map(|entry| self.processed - entry.sequence)
     }
 
     fn process_deposit(
         &mut self,
-        transaction: Transaction,
+        transaction: &Transaction,
     ) -> Result<(), TransactionProcessingError> {
-        self.validate_transaction_uniqueness(&transaction)?;
-        let amount = get_transaction_amount(&transaction)?;
+        self.validate_transaction_uniqueness(transaction)?;
+        let amount = get_transaction_amount(transaction)?;
+        let available = checked_add(self.available, amount)?;
         self.balance_changes.insert(
             transaction.tx,
             BalanceChangeEntry {
                 amount,
                 status: BalanceChangeEntryStatus::Valid,
                 ty: BalanceChangeEntryType::Deposit,
+                disputed_at: None,
+                group: transaction.group,
+                sequence: self.processed + 1,
+                memo: transaction.memo.clone(),
+                line: None,
+                withdrawal_dispute_policy: None,
+            },
+        );
+        self.available = available;
+        Ok(())
+    }
+
+    fn process_authorize(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        self.validate_transaction_uniqueness(transaction)?;
+        let amount = get_transaction_amount(transaction)?;
+        let pending = checked_add(self.pending, amount)?;
+        self.balance_changes.insert(
+            transaction.tx,
+            BalanceChangeEntry {
+                amount,
+                status: BalanceChangeEntryStatus::Pending,
+                ty: BalanceChangeEntryType::Deposit,
+                disputed_at: None,
+                group: transaction.group,
+                sequence: self.processed + 1,
+                memo: transaction.memo.clone(),
+                line: None,
+                withdrawal_dispute_policy: None,
             },
         );
-        self.available += amount;
+        self.pending = pending;
+        Ok(())
+    }
+
+    fn process_capture(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let (available, pending) = (self.available, self.pending);
+        let balance_change = self.get_balance_change_entry(transaction.tx)?;
+        if balance_change.status != BalanceChangeEntryStatus::Pending {
+            return Err(TransactionProcessingError::NotPending);
+        }
+        let amount = balance_change.amount;
+        let available = checked_add(available, amount)?;
+        let pending = checked_sub(pending, amount)?;
+        balance_change.status = BalanceChangeEntryStatus::Valid;
+        self.available = available;
+        self.pending = pending;
         Ok(())
     }
 
     fn process_withdrawal(
         &mut self,
-        transaction: Transaction,
+        transaction: &Transaction,
     ) -> Result<(), TransactionProcessingError> {
-        self.validate_transaction_uniqueness(&transaction)?;
-        let amount = get_transaction_amount(&transaction)?;
+        self.validate_transaction_uniqueness(transaction)?;
+        let amount = get_transaction_amount(transaction)?;
         if self.available < amount {
             return Err(TransactionProcessingError::NoSufficientFunds);
         }
+        let available = checked_sub(self.available, amount)?;
         self.balance_changes.insert(
             transaction.tx,
             BalanceChangeEntry {
                 amount,
                 status: BalanceChangeEntryStatus::Valid,
                 ty: BalanceChangeEntryType::Withdrawal,
+                disputed_at: None,
+                group: transaction.group,
+                sequence: self.processed + 1,
+                memo: transaction.memo.clone(),
+                line: None,
+                withdrawal_dispute_policy: None,
             },
         );
-        self.available -= amount;
+        self.available = available;
         Ok(())
     }
 
     fn process_dispute(
         &mut self,
-        transaction: Transaction,
+        transaction: &Transaction,
     ) -> Result<(), TransactionProcessingError> {
-        let mut balance_change = self.get_balance_change_entry(transaction.tx)?;
+        let (available, held) = (self.available, self.held);
+        let balance_change = self.get_balance_change_entry(transaction.tx)?;
         if balance_change.ty == BalanceChangeEntryType::Withdrawal {
             return Err(TransactionProcessingError::DisputeOnWithdrawal);
         }
-        if balance_change.status != BalanceChangeEntryStatus::Valid {
-            return Err(TransactionProcessingError::DoubleDispute);
+        match balance_change.status {
+            BalanceChangeEntryStatus::Valid => {}
+            BalanceChangeEntryStatus::Pending => {
+                return Err(TransactionProcessingError::NotCaptured)
+            }
+            BalanceChangeEntryStatus::ActiveDispute => {
+                return Err(TransactionProcessingError::DoubleDispute)
+            }
+            BalanceChangeEntryStatus::ChargedBack => {
+                return Err(TransactionProcessingError::AlreadyChargedBack)
+            }
         }
+        let amount = balance_change.amount;
+        let available = checked_sub(available, amount)?;
+        let held = checked_add(held, amount)?;
         balance_change.status = BalanceChangeEntryStatus::ActiveDispute;
+        balance_change.disputed_at = transaction.timestamp;
+        self.available = available;
+        self.held = held;
+        self.held_high_water_mark = self.held_high_water_mark.max(held);
+        self.active_disputes += 1;
+        Ok(())
+    }
+
+    fn process_withdrawal_dispute(
+        &mut self,
+        transaction: &Transaction,
+        policy: WithdrawalDisputePolicy,
+    ) -> Result<(), TransactionProcessingError> {
+        let (available, held) = (self.available, self.held);
+        let balance_change = self.get_balance_change_entry(transaction.tx)?;
+        match balance_change.status {
+            // Withdrawals are never pending.
+            BalanceChangeEntryStatus::Valid | BalanceChangeEntryStatus::Pending => {}
+            BalanceChangeEntryStatus::ActiveDispute => {
+                return Err(TransactionProcessingError::DoubleDispute)
+            }
+            BalanceChangeEntryStatus::ChargedBack => {
+                return Err(TransactionProcessingError::AlreadyChargedBack)
+            }
+        }
         let amount = balance_change.amount;
-        self.available -= amount;
-        self.held += amount;
+        let (available, held) = match policy {
+            WithdrawalDisputePolicy::Held => (available, checked_add(held, amount)?),
+            _ => (checked_add(available, amount)?, held),
+        };
+        balance_change.status = BalanceChangeEntryStatus::ActiveDispute;
+        balance_change.disputed_at = transaction.timestamp;
+        balance_change.withdrawal_dispute_policy = Some(policy);
+        self.available = available;
+        self.held = held;
+        self.held_high_water_mark = self.held_high_water_mark.max(held);
+        self.active_disputes += 1;
         Ok(())
     }
 
     fn process_resolve(
         &mut self,
-        transaction: Transaction,
+        transaction: &Transaction,
     ) -> Result<(), TransactionProcessingError> {
-        let mut balance_change = self.get_balance_change_entry(transaction.tx)?;
-        if balance_change.status != BalanceChangeEntryStatus::ActiveDispute {
-            return Err(TransactionProcessingError::DisputeNotActive);
-        }
-        balance_change.status = BalanceChangeEntryStatus::Valid;
+        let (available, held) = (self.available, self.held);
+        let balance_change = self.get_balance_change_entry(transaction.tx)?;
+        ensure_active_dispute(balance_change)?;
         let amount = balance_change.amount;
-        self.available += amount;
-        self.held -= amount;
+        let (available, held) = match balance_change.withdrawal_dispute_policy {
+            Some(WithdrawalDisputePolicy::Held) => (available, checked_sub(held, amount)?),
+            Some(_) => (checked_sub(available, amount)?, held),
+            None => (checked_add(available, amount)?, checked_sub(held, amount)?),
+        };
+        balance_change.status = BalanceChangeEntryStatus::Valid;
+        balance_change.disputed_at = None;
+        balance_change.withdrawal_dispute_policy = None;
+        self.available = available;
+        self.held = held;
+        self.active_disputes -= 1;
         Ok(())
     }
 
     fn process_chargeback(
         &mut self,
-        transaction: Transaction,
+        transaction: &Transaction,
     ) -> Result<(), TransactionProcessingError> {
-        let mut balance_change = self.get_balance_change_entry(transaction.tx)?;
-        if balance_change.status != BalanceChangeEntryStatus::ActiveDispute {
-            return Err(TransactionProcessingError::DisputeNotActive);
-        }
+        let (available, held) = self.chargeback_balances(transaction.tx)?;
+        let balance_change = self.get_balance_change_entry(transaction.tx)?;
         balance_change.status = BalanceChangeEntryStatus::ChargedBack;
-        let amount = balance_change.amount;
-        self.held -= amount;
+        balance_change.disputed_at = None;
+        balance_change.withdrawal_dispute_policy = None;
+        self.available = available;
+        self.held = held;
         self.is_frozen = true;
+        self.active_disputes -= 1;
+        Ok(())
+    }
+
+    /// State of the client after a chargeback of `tx`, without applying it.
+    /// Fails the same way the chargeback would. The engine may keep the
+    /// client unfrozen, see `Engine::with_chargeback_freeze_threshold`.
+    pub fn preview_chargeback(
+        &self,
+        tx: TxId,
+    ) -> Result<ClientSnapshot, TransactionProcessingError> {
+        let (available, held) = self.chargeback_balances(tx.0)?;
+        Ok(ClientSnapshot {
+            available,
+            held,
+            pending: self.pending,
+            is_frozen: true,
+            disputed_count: self.active_disputes - 1,
+        })
+    }
+
+    /// Available and held funds after a chargeback of `tx`.
+    fn chargeback_balances(
+        &self,
+        tx: u32,
+    ) -> Result<(Decimal, Decimal), TransactionProcessingError> {
+        let balance_change = self
+            .balance_changes
+            .get(&tx)
+            .ok_or(TransactionProcessingError::UnknownTransactionId)?;
+        ensure_active_dispute(balance_change)?;
+        let amount = balance_change.amount;
+        let (available, held) = (self.available, self.held);
+        Ok(match balance_change.withdrawal_dispute_policy {
+            Some(WithdrawalDisputePolicy::Held) => {
+                (checked_add(available, amount)?, release_held(held, amount)?)
+            }
+            Some(_) => (available, held),
+            None => (available, release_held(held, amount)?),
+        })
+    }
+
+    pub fn has_transaction(&self, tx: TxId) -> bool {
+        self.balance_changes.contains_key(&tx.0)
+    }
+
+    /// Replaces the amount of an existing deposit or withdrawal with the one
+    /// from `transaction`, adjusting available funds by the difference. Only
+    /// entries which were never disputed (or were resolved) of open accounts
+    /// can be replaced.
+    pub fn replace_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let amount = get_transaction_amount(transaction)?;
+        let balance_change = self.get_balance_change_entry(transaction.tx)?;
+        let same_type = matches!(
+            (&balance_change.ty, &transaction.ty),
+            (BalanceChangeEntryType::Deposit, TransactionType::Deposit)
+                | (
+                    BalanceChangeEntryType::Withdrawal,
+                    TransactionType::Withdrawal
+                )
+        );
+        if !same_type {
+            return Err(TransactionProcessingError::ReusedTransactionId);
+        }
+        self.set_amount(transaction.tx, amount)?;
+        self.processed += 1;
+        Ok(())
+    }
+
+    /// Corrects the amount of an existing deposit or withdrawal, adjusting
+    /// available funds by the difference. Same restrictions as
+    /// `replace_transaction` apply.
+    pub fn correct_transaction(
+        &mut self,
+        tx: TxId,
+        amount: Decimal,
+    ) -> Result<(), TransactionProcessingError> {
+        if amount.is_sign_negative() || amount.is_zero() {
+            return Err(TransactionProcessingError::NonPositiveAmount);
+        }
+        self.set_amount(tx.0, amount)
+    }
+
+    fn set_amount(&mut self, tx: u32, amount: Decimal) -> Result<(), TransactionProcessingError> {
+        if self.is_closed {
+            return Err(TransactionProcessingError::AccountClosed);
+        }
+        let available = self.available;
+        let balance_change = self.get_balance_change_entry(tx)?;
+        if balance_change.status != BalanceChangeEntryStatus::Valid {
+            return Err(TransactionProcessingError::ReplaceOnDisputedTransaction);
+        }
+        let available = match balance_change.ty {
+            BalanceChangeEntryType::Deposit => {
+                checked_add(checked_sub(available, balance_change.amount)?, amount)?
+            }
+            BalanceChangeEntryType::Withdrawal => {
+                checked_sub(checked_add(available, balance_change.amount)?, amount)?
+            }
+        };
+        if available.is_sign_negative() {
+            return Err(TransactionProcessingError::NoSufficientFunds);
+        }
+        balance_change.amount = amount;
+        self.available = available;
         Ok(())
     }
 
@@ -160,18 +783,91 @@ impl Client {
     }
 }
 
+/// Concise summary, without the transaction history printed by `Debug`.
+impl std::fmt::Display for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Client{{available={}, held={}, total={}, frozen={}, txns={}}}",
+            self.available,
+            self.held,
+            self.total(),
+            self.is_frozen,
+            self.balance_changes.len()
+        )
+    }
+}
+
+fn ensure_active_dispute(entry: &BalanceChangeEntry) -> Result<(), TransactionProcessingError> {
+    match entry.status {
+        BalanceChangeEntryStatus::ActiveDispute => Ok(()),
+        BalanceChangeEntryStatus::Pending | BalanceChangeEntryStatus::Valid => {
+            Err(TransactionProcessingError::DisputeNotActive)
+        }
+        BalanceChangeEntryStatus::ChargedBack => {
+            Err(TransactionProcessingError::AlreadyChargedBack)
+        }
+    }
+}
+
+/// Amount of a deposit or withdrawal. Direction of the balance change is
+/// implied by the transaction type, so the amount itself must be positive.
 fn get_transaction_amount(
     transaction: &Transaction,
 ) -> Result<Decimal, TransactionProcessingError> {
-    transaction
+    let amount = transaction
         .amount
-        .ok_or(TransactionProcessingError::AmountNotSpecified)
+        .ok_or(TransactionProcessingError::AmountNotSpecified)?;
+    if amount <= Decimal::new(0, 0) {
+        return Err(TransactionProcessingError::NonPositiveAmount);
+    }
+    Ok(amount)
+}
+
+fn checked_add(lhs: Decimal, rhs: Decimal) -> Result<Decimal, TransactionProcessingError> {
+    lhs.checked_add(rhs)
+        .ok_or(TransactionProcessingError::BalanceOverflow)
+}
+
+fn checked_sub(lhs: Decimal, rhs: Decimal) -> Result<Decimal, TransactionProcessingError> {
+    lhs.checked_sub(rhs)
+        .ok_or(TransactionProcessingError::BalanceUnderflow)
+}
+
+/// Subtracts a charged back amount from held funds. Held funds below the
+/// amount mean the state is already corrupted, so it's reported instead of
+/// leaving `held` negative.
+fn release_held(held: Decimal, amount: Decimal) -> Result<Decimal, TransactionProcessingError> {
+    if held < amount {
+        return Err(TransactionProcessingError::HeldUnderflow);
+    }
+    checked_sub(held, amount)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    mod with_balances {
+        use super::*;
+
+        #[test]
+        fn should_set_balances() {
+            let client = Client::with_balances(Decimal::new(15, 1), Decimal::new(5, 1), true);
+            assert_eq!(client.available, Decimal::new(15, 1));
+            assert_eq!(client.held, Decimal::new(5, 1));
+            assert_eq!(client.total(), Decimal::new(2, 0));
+            assert_eq!(client.is_frozen, true);
+            assert!(client.balance_changes.is_empty());
+        }
+
+        #[test]
+        #[should_panic]
+        fn should_panic_on_negative_held() {
+            Client::with_balances(Decimal::new(1, 0), Decimal::new(-1, 0), false);
+        }
+    }
+
     mod process_deposit {
         use super::*;
 
@@ -180,12 +876,12 @@ mod tests {
             let mut client = Client::default();
             let amount = Decimal::new(1, 4);
             client
-                .process_deposit(Transaction {
-                    amount: Some(amount),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Deposit,
-                })
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(amount),
+                ))
                 .unwrap();
             assert_eq!(client.available, amount);
             assert_eq!(client.total(), amount);
@@ -197,20 +893,20 @@ mod tests {
             let mut client = Client::default();
             let amount = Decimal::new(1, 0);
             client
-                .process_deposit(Transaction {
-                    amount: Some(amount),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Deposit,
-                })
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(amount),
+                ))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_deposit(Transaction {
-                amount: Some(amount),
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Deposit,
-            });
+            let result = client.process_deposit(&Transaction::new(
+                TransactionType::Deposit,
+                0,
+                1,
+                Some(amount),
+            ));
 
             assert_eq!(
                 TransactionProcessingError::ReusedTransactionId,
@@ -218,24 +914,96 @@ mod tests {
             );
             assert_eq!(original, client);
         }
+
+        #[test]
+        fn should_fail_on_non_positive_amount() {
+            let mut client = Client::default();
+            for amount in &[Decimal::new(-50, 1), Decimal::new(0, 0)] {
+                let result = client.process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(*amount),
+                ));
+                assert_eq!(
+                    TransactionProcessingError::NonPositiveAmount,
+                    result.err().unwrap()
+                );
+            }
+            assert_eq!(Client::default(), client);
+        }
+    }
+
+    mod process_capture {
+        use super::*;
+
+        fn authorized_client() -> Client {
+            let mut client = Client::default();
+            client
+                .process_transaction(&Transaction::new(
+                    TransactionType::Authorize,
+                    0,
+                    1,
+                    Some(Decimal::new(5, 0)),
+                ))
+                .unwrap();
+            client
+        }
+
+        #[test]
+        fn should_keep_authorized_deposit_pending() {
+            let client = authorized_client();
+            assert_eq!(client.pending, Decimal::new(5, 0));
+            assert_eq!(client.available, Decimal::new(0, 0));
+            assert_eq!(client.total(), Decimal::new(0, 0));
+            assert_eq!(client.snapshot().pending, Decimal::new(5, 0));
+        }
+
+        #[test]
+        fn should_move_pending_to_available() {
+            let mut client = authorized_client();
+            client
+                .process_transaction(&Transaction::new(TransactionType::Capture, 0, 1, None))
+                .unwrap();
+            assert_eq!(client.pending, Decimal::new(0, 0));
+            assert_eq!(client.available, Decimal::new(5, 0));
+            assert_eq!(
+                client.entry(TxId(1)).unwrap().status,
+                BalanceChangeEntryStatus::Valid
+            );
+        }
+
+        #[test]
+        fn should_fail_on_captured_or_uncaptured_transaction() {
+            let mut client = authorized_client();
+            let dispute = Transaction::new(TransactionType::Dispute, 0, 1, None);
+            assert_eq!(
+                client.process_transaction(&dispute),
+                Err(TransactionProcessingError::NotCaptured)
+            );
+            let capture = Transaction::new(TransactionType::Capture, 0, 1, None);
+            client.process_transaction(&capture).unwrap();
+            assert_eq!(
+                client.process_transaction(&capture),
+                Err(TransactionProcessingError::NotPending)
+            );
+        }
     }
+
     mod process_withdrawal {
         use super::*;
 
         #[test]
         fn should_decrease_funds() {
-            let mut client = Client {
-                available: Decimal::new(1, 0),
-                ..Default::default()
-            };
+            let mut client = Client::with_balances(Decimal::new(1, 0), Decimal::new(0, 0), false);
             let amount = Decimal::new(1, 4);
             client
-                .process_withdrawal(Transaction {
-                    amount: Some(amount),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Withdrawal,
-                })
+                .process_withdrawal(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    1,
+                    Some(amount),
+                ))
                 .unwrap();
             let expected = Decimal::new(9999, 4);
             assert_eq!(client.available, expected);
@@ -245,18 +1013,15 @@ mod tests {
 
         #[test]
         fn should_fail_on_not_enough_funds() {
-            let mut client = Client {
-                available: Decimal::new(1, 0),
-                ..Default::default()
-            };
+            let mut client = Client::with_balances(Decimal::new(1, 0), Decimal::new(0, 0), false);
             let amount = Decimal::new(2, 0);
             let original = client.clone();
-            let result = client.process_withdrawal(Transaction {
-                amount: Some(amount),
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Withdrawal,
-            });
+            let result = client.process_withdrawal(&Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                1,
+                Some(amount),
+            ));
             assert_eq!(
                 TransactionProcessingError::NoSufficientFunds,
                 result.err().unwrap()
@@ -264,27 +1029,40 @@ mod tests {
             assert_eq!(original, client);
         }
         #[test]
+        fn should_fail_on_negative_amount() {
+            let mut client = Client::with_balances(Decimal::new(1, 0), Decimal::new(0, 0), false);
+            let original = client.clone();
+            let result = client.process_withdrawal(&Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                1,
+                Some(Decimal::new(-50, 1)),
+            ));
+            assert_eq!(
+                TransactionProcessingError::NonPositiveAmount,
+                result.err().unwrap()
+            );
+            assert_eq!(original, client);
+        }
+        #[test]
         fn should_fail_on_reused_transaction_id() {
-            let mut client = Client {
-                available: Decimal::new(10, 0),
-                ..Default::default()
-            };
+            let mut client = Client::with_balances(Decimal::new(10, 0), Decimal::new(0, 0), false);
             let amount = Decimal::new(1, 0);
             client
-                .process_withdrawal(Transaction {
-                    amount: Some(amount),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Withdrawal,
-                })
+                .process_withdrawal(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    1,
+                    Some(amount),
+                ))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_withdrawal(Transaction {
-                amount: Some(amount),
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Withdrawal,
-            });
+            let result = client.process_withdrawal(&Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                1,
+                Some(amount),
+            ));
 
             assert_eq!(
                 TransactionProcessingError::ReusedTransactionId,
@@ -301,12 +1079,12 @@ mod tests {
         fn create_test_client() -> Client {
             let mut client = Client::default();
             client
-                .process_deposit(Transaction {
-                    amount: Some(Decimal::new(1, 0)),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Deposit,
-                })
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(1, 0)),
+                ))
                 .unwrap();
             client
         }
@@ -314,12 +1092,7 @@ mod tests {
         fn should_block_funds() {
             let mut client = create_test_client();
             client
-                .process_dispute(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Dispute,
-                })
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
                 .unwrap();
             assert_eq!(client.available, Decimal::new(0, 0));
             assert_eq!(client.held, Decimal::new(1, 0));
@@ -329,12 +1102,7 @@ mod tests {
         fn should_change_entry_status() {
             let mut client = create_test_client();
             client
-                .process_dispute(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Dispute,
-                })
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
                 .unwrap();
             assert_eq!(client.balance_changes.len(), 1);
             assert_eq!(
@@ -346,20 +1114,16 @@ mod tests {
         fn should_fail_on_withdrawal() {
             let mut client = create_test_client();
             client
-                .process_withdrawal(Transaction {
-                    amount: Some(Decimal::new(1, 0)),
-                    client: 0,
-                    tx: 2,
-                    ty: TransactionType::Withdrawal,
-                })
+                .process_withdrawal(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    2,
+                    Some(Decimal::new(1, 0)),
+                ))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_dispute(Transaction {
-                amount: None,
-                client: 0,
-                tx: 2,
-                ty: TransactionType::Dispute,
-            });
+            let result =
+                client.process_dispute(&Transaction::new(TransactionType::Dispute, 0, 2, None));
 
             assert_eq!(
                 TransactionProcessingError::DisputeOnWithdrawal,
@@ -371,20 +1135,11 @@ mod tests {
         fn should_fail_on_double_dispute() {
             let mut client = create_test_client();
             client
-                .process_dispute(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Dispute,
-                })
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_dispute(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Dispute,
-            });
+            let result =
+                client.process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None));
 
             assert_eq!(
                 TransactionProcessingError::DoubleDispute,
@@ -396,31 +1151,17 @@ mod tests {
         fn should_fail_on_chargeback_transaction() {
             let mut client = create_test_client();
             client
-                .process_dispute(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Dispute,
-                })
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
                 .unwrap();
             client
-                .process_chargeback(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Chargeback,
-                })
+                .process_chargeback(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_dispute(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Dispute,
-            });
+            let result =
+                client.process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None));
 
             assert_eq!(
-                TransactionProcessingError::DoubleDispute,
+                TransactionProcessingError::AlreadyChargedBack,
                 result.err().unwrap()
             );
             assert_eq!(original, client);
@@ -428,12 +1169,8 @@ mod tests {
         #[test]
         fn should_fail_on_nonexisting_transaction() {
             let mut client = Client::default();
-            let result = client.process_dispute(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Dispute,
-            });
+            let result =
+                client.process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None));
             let original = client.clone();
             assert_eq!(
                 TransactionProcessingError::UnknownTransactionId,
@@ -449,20 +1186,15 @@ mod tests {
         fn create_test_client() -> Client {
             let mut client = Client::default();
             client
-                .process_deposit(Transaction {
-                    amount: Some(Decimal::new(1, 0)),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Deposit,
-                })
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(1, 0)),
+                ))
                 .unwrap();
             client
-                .process_dispute(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Dispute,
-                })
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
                 .unwrap();
             client
         }
@@ -471,27 +1203,40 @@ mod tests {
         fn should_make_funds_available() {
             let mut client = create_test_client();
             client
-                .process_resolve(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Resolve,
-                })
+                .process_resolve(&Transaction::new(TransactionType::Resolve, 0, 1, None))
                 .unwrap();
             assert_eq!(client.available, Decimal::new(1, 0));
             assert_eq!(client.held, Decimal::new(0, 0));
             assert_eq!(client.total(), Decimal::new(1, 0));
         }
         #[test]
+        fn should_leave_exactly_zero_held() {
+            let mut client = Client::default();
+            client
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(1234, 4)),
+                ))
+                .unwrap();
+            let total = client.total();
+            client
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
+                .unwrap();
+            assert_eq!(client.total(), total);
+            client
+                .process_resolve(&Transaction::new(TransactionType::Resolve, 0, 1, None))
+                .unwrap();
+            assert!(client.held.is_zero());
+            assert_eq!(client.available, Decimal::new(1234, 4));
+            assert_eq!(client.total(), total);
+        }
+        #[test]
         fn should_change_entry_status() {
             let mut client = create_test_client();
             client
-                .process_resolve(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Resolve,
-                })
+                .process_resolve(&Transaction::new(TransactionType::Resolve, 0, 1, None))
                 .unwrap();
             assert_eq!(client.balance_changes.len(), 1);
             assert_eq!(
@@ -503,20 +1248,79 @@ mod tests {
         fn should_fail_on_valid_transaction() {
             let mut client = Client::default();
             client
-                .process_deposit(Transaction {
-                    amount: Some(Decimal::new(1, 0)),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Deposit,
-                })
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(1, 0)),
+                ))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_resolve(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Resolve,
-            });
+            let result =
+                client.process_resolve(&Transaction::new(TransactionType::Resolve, 0, 1, None));
+            assert_eq!(
+                TransactionProcessingError::DisputeNotActive,
+                result.err().unwrap()
+            );
+            assert_eq!(original, client);
+        }
+        #[test]
+        fn should_fail_on_withdrawal() {
+            let mut client = create_test_client();
+            client
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    2,
+                    Some(Decimal::new(2, 0)),
+                ))
+                .unwrap();
+            client
+                .process_withdrawal(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    3,
+                    Some(Decimal::new(1, 0)),
+                ))
+                .unwrap();
+            let original = client.clone();
+            let result =
+                client.process_resolve(&Transaction::new(TransactionType::Resolve, 0, 3, None));
+            assert_eq!(
+                TransactionProcessingError::DisputeNotActive,
+                result.err().unwrap()
+            );
+            assert_eq!(original, client);
+        }
+        #[test]
+        fn should_fail_on_withdrawal_after_rejected_dispute() {
+            let mut client = create_test_client();
+            client
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    2,
+                    Some(Decimal::new(2, 0)),
+                ))
+                .unwrap();
+            client
+                .process_withdrawal(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    3,
+                    Some(Decimal::new(1, 0)),
+                ))
+                .unwrap();
+            assert_eq!(
+                TransactionProcessingError::DisputeOnWithdrawal,
+                client
+                    .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 3, None))
+                    .err()
+                    .unwrap()
+            );
+            let original = client.clone();
+            let result =
+                client.process_resolve(&Transaction::new(TransactionType::Resolve, 0, 3, None));
             assert_eq!(
                 TransactionProcessingError::DisputeNotActive,
                 result.err().unwrap()
@@ -527,23 +1331,14 @@ mod tests {
         fn should_fail_on_chargeback_transaction() {
             let mut client = create_test_client();
             client
-                .process_chargeback(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Chargeback,
-                })
+                .process_chargeback(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_resolve(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Resolve,
-            });
+            let result =
+                client.process_resolve(&Transaction::new(TransactionType::Resolve, 0, 1, None));
 
             assert_eq!(
-                TransactionProcessingError::DisputeNotActive,
+                TransactionProcessingError::AlreadyChargedBack,
                 result.err().unwrap()
             );
             assert_eq!(original, client);
@@ -552,12 +1347,8 @@ mod tests {
         fn should_fail_on_nonexisting_transaction() {
             let mut client = Client::default();
             let original = client.clone();
-            let result = client.process_resolve(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Resolve,
-            });
+            let result =
+                client.process_resolve(&Transaction::new(TransactionType::Resolve, 0, 1, None));
             assert_eq!(
                 TransactionProcessingError::UnknownTransactionId,
                 result.err().unwrap()
@@ -571,20 +1362,15 @@ mod tests {
         fn create_test_client() -> Client {
             let mut client = Client::default();
             client
-                .process_deposit(Transaction {
-                    amount: Some(Decimal::new(1, 0)),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Deposit,
-                })
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(1, 0)),
+                ))
                 .unwrap();
             client
-                .process_dispute(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Dispute,
-                })
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
                 .unwrap();
             client
         }
@@ -592,12 +1378,7 @@ mod tests {
         fn should_reverse_transaction() {
             let mut client = create_test_client();
             client
-                .process_chargeback(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Chargeback,
-                })
+                .process_chargeback(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
                 .unwrap();
             assert_eq!(client.available, Decimal::new(0, 0));
             assert_eq!(client.held, Decimal::new(0, 0));
@@ -607,12 +1388,7 @@ mod tests {
         fn should_change_entry_status() {
             let mut client = create_test_client();
             client
-                .process_chargeback(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Chargeback,
-                })
+                .process_chargeback(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
                 .unwrap();
             assert_eq!(client.balance_changes.len(), 1);
             assert_eq!(
@@ -624,33 +1400,47 @@ mod tests {
         fn should_freeze_account() {
             let mut client = create_test_client();
             client
-                .process_chargeback(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Chargeback,
-                })
+                .process_chargeback(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
                 .unwrap();
             assert_eq!(client.is_frozen, true);
         }
         #[test]
+        fn should_fail_on_held_underflow() {
+            let mut client = create_test_client();
+            client.held = Decimal::new(5, 1);
+            let original = client.clone();
+            let result = client.process_chargeback(&Transaction::new(
+                TransactionType::Chargeback,
+                0,
+                1,
+                None,
+            ));
+
+            assert_eq!(
+                TransactionProcessingError::HeldUnderflow,
+                result.err().unwrap()
+            );
+            assert_eq!(original, client);
+            assert!(!client.is_frozen);
+        }
+        #[test]
         fn should_fail_on_valid_transaction() {
             let mut client = Client::default();
             client
-                .process_deposit(Transaction {
-                    amount: Some(Decimal::new(1, 0)),
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Deposit,
-                })
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(1, 0)),
+                ))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_chargeback(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Chargeback,
-            });
+            let result = client.process_chargeback(&Transaction::new(
+                TransactionType::Chargeback,
+                0,
+                1,
+                None,
+            ));
 
             assert_eq!(
                 TransactionProcessingError::DisputeNotActive,
@@ -662,22 +1452,17 @@ mod tests {
         fn should_fail_on_chargeback_transaction() {
             let mut client = create_test_client();
             client
-                .process_chargeback(Transaction {
-                    amount: None,
-                    client: 0,
-                    tx: 1,
-                    ty: TransactionType::Chargeback,
-                })
+                .process_chargeback(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
                 .unwrap();
             let original = client.clone();
-            let result = client.process_chargeback(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Chargeback,
-            });
+            let result = client.process_chargeback(&Transaction::new(
+                TransactionType::Chargeback,
+                0,
+                1,
+                None,
+            ));
             assert_eq!(
-                TransactionProcessingError::DisputeNotActive,
+                TransactionProcessingError::AlreadyChargedBack,
                 result.err().unwrap()
             );
             assert_eq!(original, client);
@@ -686,12 +1471,12 @@ mod tests {
         fn should_fail_on_nonexisting_transaction() {
             let mut client = Client::default();
             let original = client.clone();
-            let result = client.process_chargeback(Transaction {
-                amount: None,
-                client: 0,
-                tx: 1,
-                ty: TransactionType::Chargeback,
-            });
+            let result = client.process_chargeback(&Transaction::new(
+                TransactionType::Chargeback,
+                0,
+                1,
+                None,
+            ));
             assert_eq!(
                 TransactionProcessingError::UnknownTransactionId,
                 result.err().unwrap()
@@ -699,4 +1484,495 @@ mod tests {
             assert_eq!(original, client);
         }
     }
+
+    mod max_active_dispute {
+        use super::*;
+
+        fn deposit(client: &mut Client, tx: u32, amount: Decimal) {
+            client
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    tx,
+                    Some(amount),
+                ))
+                .unwrap();
+        }
+
+        fn dispute(client: &mut Client, tx: u32) {
+            client
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, tx, None))
+                .unwrap();
+        }
+
+        #[test]
+        fn should_return_largest_dispute() {
+            let mut client = Client::default();
+            deposit(&mut client, 1, Decimal::new(1, 0));
+            deposit(&mut client, 2, Decimal::new(5, 0));
+            deposit(&mut client, 3, Decimal::new(10, 0));
+            dispute(&mut client, 1);
+            dispute(&mut client, 2);
+            assert_eq!(client.max_active_dispute(), Some((2, Decimal::new(5, 0))));
+        }
+
+        #[test]
+        fn should_return_none_without_active_disputes() {
+            let mut client = Client::default();
+            deposit(&mut client, 1, Decimal::new(1, 0));
+            assert_eq!(client.max_active_dispute(), None);
+        }
+    }
+
+    mod preview_chargeback {
+        use super::*;
+
+        #[test]
+        fn should_preview_without_applying() {
+            let mut client = Client::default();
+            for transaction in &[
+                Transaction::new(TransactionType::Deposit, 0, 1, Some(Decimal::new(10, 0))),
+                Transaction::new(TransactionType::Deposit, 0, 2, Some(Decimal::new(4, 0))),
+                Transaction::new(TransactionType::Dispute, 0, 2, None),
+            ] {
+                client.process_transaction(transaction).unwrap();
+            }
+            let original = client.clone();
+
+            assert_eq!(
+                client.preview_chargeback(TxId(2)),
+                Ok(ClientSnapshot {
+                    available: Decimal::new(10, 0),
+                    held: Decimal::new(0, 0),
+                    pending: Decimal::new(0, 0),
+                    is_frozen: true,
+                    disputed_count: 0,
+                })
+            );
+            assert_eq!(client, original);
+            assert_eq!(
+                client.preview_chargeback(TxId(1)),
+                Err(TransactionProcessingError::DisputeNotActive)
+            );
+        }
+    }
+
+    mod estimated_bytes {
+        use super::*;
+
+        #[test]
+        fn should_grow_with_history() {
+            let mut client = Client::default();
+            let empty = client.estimated_bytes();
+            for tx in 1..=100 {
+                client
+                    .process_transaction(&Transaction::new(
+                        TransactionType::Deposit,
+                        0,
+                        tx,
+                        Some(Decimal::new(1, 0)),
+                    ))
+                    .unwrap();
+            }
+            assert!(client.estimated_bytes() > empty);
+        }
+    }
+
+    mod held_high_water_mark {
+        use super::*;
+
+        #[test]
+        fn should_keep_highest_held_amount() {
+            let mut client = Client::default();
+            for transaction in &[
+                Transaction::new(TransactionType::Deposit, 0, 1, Some(Decimal::new(100, 0))),
+                Transaction::new(TransactionType::Deposit, 0, 2, Some(Decimal::new(10, 0))),
+                Transaction::new(TransactionType::Dispute, 0, 1, None),
+                Transaction::new(TransactionType::Resolve, 0, 1, None),
+                Transaction::new(TransactionType::Dispute, 0, 2, None),
+            ] {
+                client.process_transaction(transaction).unwrap();
+            }
+            assert_eq!(client.held, Decimal::new(10, 0));
+            assert_eq!(client.held_high_water_mark(), Decimal::new(100, 0));
+        }
+    }
+
+    mod held_ratio {
+        use super::*;
+
+        #[test]
+        fn should_return_fraction_of_total() {
+            let client = Client::with_balances(Decimal::new(5, 0), Decimal::new(5, 0), false);
+            assert_eq!(client.held_ratio(), Some(Decimal::new(5, 1)));
+        }
+
+        #[test]
+        fn should_return_none_for_zero_total() {
+            assert_eq!(Client::default().held_ratio(), None);
+        }
+    }
+
+    mod expected_balances {
+        use super::*;
+
+        fn client_with(transactions: &[Transaction]) -> Client {
+            let mut client = Client::default();
+            for transaction in transactions {
+                client.process_transaction(transaction).unwrap();
+            }
+            client
+        }
+
+        #[test]
+        fn should_replay_history() {
+            let client = client_with(&[
+                Transaction::new(TransactionType::Deposit, 0, 1, Some(Decimal::new(10, 0))),
+                Transaction::new(TransactionType::Withdrawal, 0, 2, Some(Decimal::new(4, 0))),
+                Transaction::new(TransactionType::Deposit, 0, 3, Some(Decimal::new(3, 0))),
+                Transaction::new(TransactionType::Dispute, 0, 3, None),
+            ]);
+            assert_eq!(
+                client.expected_balances(),
+                Ok((Decimal::new(6, 0), Decimal::new(3, 0)))
+            );
+        }
+
+        #[test]
+        fn should_report_replay_out_of_range() {
+            // Withdrawals come first in tx id order.
+            let client = client_with(&[
+                Transaction::new(TransactionType::Deposit, 0, 3, Some(Decimal::MAX)),
+                Transaction::new(TransactionType::Withdrawal, 0, 1, Some(Decimal::MAX)),
+                Transaction::new(TransactionType::Deposit, 0, 4, Some(Decimal::new(1, 0))),
+                Transaction::new(TransactionType::Withdrawal, 0, 2, Some(Decimal::new(1, 0))),
+            ]);
+            assert_eq!(
+                client.expected_balances(),
+                Err(TransactionProcessingError::BalanceUnderflow)
+            );
+        }
+    }
+
+    mod disputed_amount {
+        use super::*;
+
+        #[test]
+        fn should_sum_active_disputes() {
+            let mut client = Client::default();
+            for transaction in &[
+                Transaction::new(TransactionType::Deposit, 0, 1, Some(Decimal::new(10, 0))),
+                Transaction::new(TransactionType::Deposit, 0, 2, Some(Decimal::new(5, 0))),
+                Transaction::new(TransactionType::Withdrawal, 0, 3, Some(Decimal::new(4, 0))),
+                Transaction::new(TransactionType::Dispute, 0, 1, None),
+                Transaction::new(TransactionType::Dispute, 0, 3, None),
+            ] {
+                client
+                    .process_transaction_with_policy(
+                        transaction,
+                        WithdrawalDisputePolicy::Available,
+                    )
+                    .unwrap();
+            }
+            assert_eq!(client.disputed_amount(), Decimal::new(14, 0));
+            assert_eq!(client.held, Decimal::new(10, 0));
+        }
+    }
+
+    mod verify_held_consistency {
+        use super::*;
+
+        fn create_test_client() -> Client {
+            let mut client = Client::default();
+            for tx in 1..=3 {
+                client
+                    .process_deposit(&Transaction::new(
+                        TransactionType::Deposit,
+                        0,
+                        tx,
+                        Some(Decimal::new(tx as i64, 0)),
+                    ))
+                    .unwrap();
+            }
+            for tx in 1..=2 {
+                client
+                    .process_dispute(&Transaction::new(TransactionType::Dispute, 0, tx, None))
+                    .unwrap();
+            }
+            client
+        }
+
+        #[test]
+        fn should_pass_after_disputes() {
+            let client = create_test_client();
+            assert_eq!(client.held, Decimal::new(3, 0));
+            assert_eq!(client.verify_held_consistency(), Ok(()));
+        }
+
+        #[test]
+        fn should_detect_drift() {
+            let mut client = create_test_client();
+            client.held += Decimal::new(1, 1);
+            assert_eq!(
+                client.verify_held_consistency(),
+                Err(InvariantViolation::HeldMismatch {
+                    expected: Decimal::new(3, 0),
+                    actual: Decimal::new(31, 1),
+                })
+            );
+        }
+    }
+
+    mod optimistic_available {
+        use super::*;
+
+        #[test]
+        fn should_include_disputed_deposits() {
+            let mut client = Client::default();
+            for tx in 1..=2 {
+                client
+                    .process_deposit(&Transaction::new(
+                        TransactionType::Deposit,
+                        0,
+                        tx,
+                        Some(Decimal::new(i64::from(tx), 0)),
+                    ))
+                    .unwrap();
+            }
+            client
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 2, None))
+                .unwrap();
+            assert_eq!(client.available, Decimal::new(1, 0));
+            assert_eq!(client.optimistic_available(), Decimal::new(3, 0));
+        }
+    }
+
+    mod status_flags {
+        use super::*;
+
+        #[test]
+        fn should_be_empty_for_new_client() {
+            assert_eq!(Client::default().status_flags(), 0);
+        }
+
+        #[test]
+        fn should_report_frozen_client_with_active_dispute() {
+            let mut client = Client::default();
+            for tx in 1..=2 {
+                client
+                    .process_deposit(&Transaction::new(
+                        TransactionType::Deposit,
+                        0,
+                        tx,
+                        Some(Decimal::new(1, 0)),
+                    ))
+                    .unwrap();
+                client
+                    .process_dispute(&Transaction::new(TransactionType::Dispute, 0, tx, None))
+                    .unwrap();
+            }
+            client
+                .process_chargeback(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
+                .unwrap();
+            assert_eq!(
+                client.status_flags(),
+                STATUS_FROZEN | STATUS_ACTIVE_DISPUTES | STATUS_CHARGEBACKS
+            );
+        }
+    }
+
+    mod history {
+        use super::*;
+
+        #[test]
+        fn should_list_entries_by_tx_id() {
+            let mut client = Client::default();
+            for tx in &[3, 1, 2] {
+                client
+                    .process_deposit(&Transaction::new(
+                        TransactionType::Deposit,
+                        0,
+                        *tx,
+                        Some(Decimal::new(*tx as i64, 0)),
+                    ))
+                    .unwrap();
+            }
+            let history = client.history();
+            assert_eq!(
+                history.iter().map(|(tx, _)| *tx).collect::<Vec<_>>(),
+                vec![1, 2, 3]
+            );
+            assert_eq!(history[2].1.amount, Decimal::new(3, 0));
+            assert_eq!(history[2].1.ty, BalanceChangeEntryType::Deposit);
+        }
+    }
+
+    mod memo {
+        use super::*;
+
+        #[test]
+        fn should_keep_memo_in_history() {
+            let mut client = Client::default();
+            client
+                .process_deposit(&Transaction {
+                    memo: Some("invoice 42".to_string()),
+                    ..Transaction::new(TransactionType::Deposit, 0, 1, Some(Decimal::new(1, 0)))
+                })
+                .unwrap();
+            client
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    2,
+                    Some(Decimal::new(1, 0)),
+                ))
+                .unwrap();
+            let history = client.history();
+            assert_eq!(history[0].1.memo.as_deref(), Some("invoice 42"));
+            assert_eq!(history[1].1.memo, None);
+            assert_eq!(client.available, Decimal::new(2, 0));
+        }
+    }
+
+    mod replace_transaction {
+        use super::*;
+
+        fn create_test_client() -> Client {
+            let mut client = Client::default();
+            client
+                .process_deposit(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(10, 0)),
+                ))
+                .unwrap();
+            client
+                .process_withdrawal(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    2,
+                    Some(Decimal::new(4, 0)),
+                ))
+                .unwrap();
+            client
+        }
+
+        #[test]
+        fn should_adjust_deposit_by_delta() {
+            let mut client = create_test_client();
+            client
+                .replace_transaction(&Transaction::new(
+                    TransactionType::Deposit,
+                    0,
+                    1,
+                    Some(Decimal::new(12, 0)),
+                ))
+                .unwrap();
+            assert_eq!(client.available, Decimal::new(8, 0));
+            assert_eq!(
+                client.balance_changes.get(&1).unwrap().amount,
+                Decimal::new(12, 0)
+            );
+        }
+
+        #[test]
+        fn should_adjust_withdrawal_by_delta() {
+            let mut client = create_test_client();
+            client
+                .replace_transaction(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    2,
+                    Some(Decimal::new(1, 0)),
+                ))
+                .unwrap();
+            assert_eq!(client.available, Decimal::new(9, 0));
+        }
+
+        #[test]
+        fn should_fail_on_insufficient_funds() {
+            let mut client = create_test_client();
+            let original = client.clone();
+            let result = client.replace_transaction(&Transaction::new(
+                TransactionType::Deposit,
+                0,
+                1,
+                Some(Decimal::new(3, 0)),
+            ));
+            assert_eq!(
+                TransactionProcessingError::NoSufficientFunds,
+                result.err().unwrap()
+            );
+            assert_eq!(original, client);
+        }
+
+        #[test]
+        fn should_fail_on_different_type() {
+            let mut client = create_test_client();
+            let original = client.clone();
+            let result = client.replace_transaction(&Transaction::new(
+                TransactionType::Withdrawal,
+                0,
+                1,
+                Some(Decimal::new(1, 0)),
+            ));
+            assert_eq!(
+                TransactionProcessingError::ReusedTransactionId,
+                result.err().unwrap()
+            );
+            assert_eq!(original, client);
+        }
+
+        #[test]
+        fn should_fail_on_disputed_transaction() {
+            let mut client = create_test_client();
+            client
+                .process_dispute(&Transaction::new(TransactionType::Dispute, 0, 1, None))
+                .unwrap();
+            let original = client.clone();
+            let result = client.replace_transaction(&Transaction::new(
+                TransactionType::Deposit,
+                0,
+                1,
+                Some(Decimal::new(12, 0)),
+            ));
+            assert_eq!(
+                TransactionProcessingError::ReplaceOnDisputedTransaction,
+                result.err().unwrap()
+            );
+            assert_eq!(original, client);
+        }
+    }
+
+    mod display {
+        use super::*;
+
+        #[test]
+        fn should_summarize_client() {
+            let mut client = Client::default();
+            for (tx, amount) in &[(1, Decimal::new(10, 1)), (2, Decimal::new(15, 1))] {
+                client
+                    .process_deposit(&Transaction::new(
+                        TransactionType::Deposit,
+                        0,
+                        *tx,
+                        Some(*amount),
+                    ))
+                    .unwrap();
+            }
+            client
+                .process_withdrawal(&Transaction::new(
+                    TransactionType::Withdrawal,
+                    0,
+                    3,
+                    Some(Decimal::new(10, 1)),
+                ))
+                .unwrap();
+            assert_eq!(
+                client.to_string(),
+                "Client{available=1.5, held=0, total=1.5, frozen=false, txns=3}"
+            );
+        }
+    }
 }
@@ -0,0 +1,3999 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    io::Write,
+    iter::Peekable,
+    rc::Rc,
+    sync::mpsc::Receiver,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    client::{
+        BalanceChangeEntry, BalanceChangeEntryStatus, BalanceChangeEntryType, Client,
+        ClientSnapshot, WithdrawalDisputePolicy,
+    },
+    errors::{
+        BatchError, CloseAccountError, CloseBlocker, LedgerImbalance, OutputError,
+        ReconciliationError, TransactionProcessingError,
+    },
+    ids::{ClientId, TxId},
+    input_types::{BatchItem, Correction, Transaction, TransactionType},
+    output::{write_header, write_row, OutputSink},
+};
+
+/// Snapshots of all clients, by client id.
+pub type ClientList = BTreeMap<u16, ClientSnapshot>;
+
+/// Rejected transactions of a client, by rejection reason.
+pub type ClientErrorCounts = HashMap<TransactionProcessingError, usize>;
+
+/// Change of a client's state relative to a baseline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientDiff {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    /// New value of the frozen flag, if it changed.
+    pub is_frozen: Option<bool>,
+    /// Client wasn't present in the baseline.
+    pub is_new: bool,
+}
+
+/// Issues found by `Engine::dry_run_consistency`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    pub transactions: usize,
+    /// Disputes, resolves and chargebacks referencing an unknown tx.
+    pub unknown_tx_references: usize,
+    pub reused_tx_ids: usize,
+    /// Withdrawals exceeding available funds at that point of the feed.
+    pub insufficient_funds: usize,
+    pub missing_amounts: usize,
+    /// Disputes, resolves and chargebacks not matching the state of the
+    /// referenced tx (e.g. resolve without a dispute).
+    pub invalid_dispute_transitions: usize,
+    pub other: usize,
+}
+
+impl ConsistencyReport {
+    pub fn rejected(&self) -> usize {
+        self.unknown_tx_references
+            + self.reused_tx_ids
+            + self.insufficient_funds
+            + self.missing_amounts
+            + self.invalid_dispute_transitions
+            + self.other
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.rejected() == 0
+    }
+
+    fn record(&mut self, err: &TransactionProcessingError) {
+        match err {
+            TransactionProcessingError::UnknownTransactionId => self.unknown_tx_references += 1,
+            TransactionProcessingError::ReusedTransactionId => self.reused_tx_ids += 1,
+            TransactionProcessingError::NoSufficientFunds => self.insufficient_funds += 1,
+            TransactionProcessingError::AmountNotSpecified => self.missing_amounts += 1,
+            TransactionProcessingError::DoubleDispute
+            | TransactionProcessingError::DisputeNotActive
+            | TransactionProcessingError::AlreadyChargedBack
+            | TransactionProcessingError::DisputeOnWithdrawal => {
+                self.invalid_dispute_transitions += 1
+            }
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// Financial summary of successfully processed transactions, see
+/// `Engine::stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessingStats {
+    pub deposited: Decimal,
+    pub withdrawn: Decimal,
+    pub disputed: Decimal,
+    pub charged_back: Decimal,
+}
+
+impl ProcessingStats {
+    /// Accounts a transaction given the status of the referenced entry
+    /// before it and the entry after it. No-ops (e.g. ignored reused tx ids
+    /// or idempotent disputes) and replacements don't change the totals.
+    fn record(
+        &mut self,
+        ty: &TransactionType,
+        before: Option<BalanceChangeEntryStatus>,
+        after: Option<&BalanceChangeEntry>,
+    ) {
+        let after = match after {
+            Some(after) => after,
+            None => return,
+        };
+        let total = match (ty, before, &after.status) {
+            (TransactionType::Deposit, None, _) => &mut self.deposited,
+            (TransactionType::Withdrawal, None, _) => &mut self.withdrawn,
+            (
+                TransactionType::Capture,
+                Some(BalanceChangeEntryStatus::Pending),
+                BalanceChangeEntryStatus::Valid,
+            ) => &mut self.deposited,
+            (
+                TransactionType::Dispute,
+                Some(BalanceChangeEntryStatus::Valid),
+                BalanceChangeEntryStatus::ActiveDispute,
+            ) => &mut self.disputed,
+            (
+                TransactionType::Chargeback,
+                Some(BalanceChangeEntryStatus::ActiveDispute),
+                BalanceChangeEntryStatus::ChargedBack,
+            ) => &mut self.charged_back,
+            _ => return,
+        };
+        // The totals are informational only, so saturate instead of
+        // rejecting a transaction the client balances could absorb.
+        *total = total.checked_add(after.amount).unwrap_or(Decimal::MAX);
+    }
+}
+
+/// Entry of the trace produced by `Engine::process_and_log`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessedTransaction {
+    pub transaction: Transaction,
+    pub outcome: Result<(), TransactionProcessingError>,
+    /// State of the client afterwards, `None` if it was evicted.
+    pub client: Option<ClientSnapshot>,
+}
+
+/// Audit record of a successfully processed transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateTransition {
+    pub tx: u32,
+    pub client: u16,
+    pub before: ClientSnapshot,
+    pub after: ClientSnapshot,
+    pub memo: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Config {
+    abort_on_overflow: bool,
+    audit: bool,
+    idempotent_disputes: bool,
+    reused_tx_policy: ReusedTxPolicy,
+    settlement_delay: u64,
+    disputes_disabled: bool,
+    chargeback_freeze_threshold: usize,
+    max_held_per_client: Option<Decimal>,
+    internal_scale: Option<u32>,
+    withdrawal_dispute_policy: WithdrawalDisputePolicy,
+    idempotency_keys: bool,
+    min_deposit: Decimal,
+    monotonic_tx: bool,
+    inactive_client_policy: InactiveClientPolicy,
+    freeze_policy: FreezePolicy,
+    collect_rejects: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            abort_on_overflow: false,
+            audit: false,
+            idempotent_disputes: false,
+            reused_tx_policy: ReusedTxPolicy::default(),
+            settlement_delay: 0,
+            disputes_disabled: false,
+            chargeback_freeze_threshold: 1,
+            max_held_per_client: None,
+            internal_scale: None,
+            withdrawal_dispute_policy: WithdrawalDisputePolicy::default(),
+            idempotency_keys: false,
+            min_deposit: Decimal::new(0, 0),
+            monotonic_tx: false,
+            inactive_client_policy: InactiveClientPolicy::default(),
+            freeze_policy: FreezePolicy::default(),
+            collect_rejects: false,
+        }
+    }
+}
+
+/// Transactions rejected with `AccountFrozen` while the client is frozen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FreezePolicy {
+    /// Nothing, freezing only flags the account.
+    Allow,
+    /// Transactions moving funds in or out of the account. Disputes and their
+    /// outcomes are still allowed for investigation.
+    BlockMoneyMovement,
+    /// All transactions.
+    BlockAll,
+}
+
+impl FreezePolicy {
+    fn blocks(self, ty: &TransactionType) -> bool {
+        match self {
+            FreezePolicy::Allow => false,
+            FreezePolicy::BlockMoneyMovement => matches!(
+                ty,
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Authorize
+                    | TransactionType::Capture
+            ),
+            FreezePolicy::BlockAll => true,
+        }
+    }
+}
+
+impl Default for FreezePolicy {
+    fn default() -> Self {
+        FreezePolicy::BlockMoneyMovement
+    }
+}
+
+/// Handling of deposits for clients which were closed or evicted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InactiveClientPolicy {
+    /// Reject with `AccountClosed` or `EvictedClient`.
+    Reject,
+    /// Start over with a fresh client, dropping the inactive one once the
+    /// deposit succeeds.
+    Reopen,
+}
+
+impl Default for InactiveClientPolicy {
+    fn default() -> Self {
+        InactiveClientPolicy::Reject
+    }
+}
+
+/// Handling of deposits and withdrawals reusing an already known tx id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReusedTxPolicy {
+    /// Reject with `ReusedTransactionId`.
+    Reject,
+    /// Accept as a no-op, keeping the original transaction.
+    Ignore,
+    /// Treat as a correction of the original transaction ("last write wins"),
+    /// see `Client::replace_transaction`.
+    Replace,
+}
+
+impl Default for ReusedTxPolicy {
+    fn default() -> Self {
+        ReusedTxPolicy::Reject
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct State {
+    clients: BTreeMap<u16, Client>,
+    evicted: BTreeSet<u16>,
+    audit_log: Vec<StateTransition>,
+    /// Clients changed since the last `take_dirty` or `write_dirty`.
+    dirty: BTreeSet<u16>,
+    rejections: HashMap<u16, ClientErrorCounts>,
+    idempotency_keys: HashSet<String>,
+    /// Last deposit or withdrawal tx id of each client, tracked only with
+    /// `Config::monotonic_tx`.
+    last_tx: HashMap<u16, u32>,
+    stats: ProcessingStats,
+    /// Client which first recorded each deposit or withdrawal tx id, see
+    /// `Engine::owner_of`. Entries of evicted, reopened or replaced clients
+    /// are dropped along with them.
+    tx_index: HashMap<u32, u16>,
+    /// Rejected transactions, collected only with `Config::collect_rejects`.
+    rejects: Vec<RejectedTransaction>,
+}
+
+/// Transaction rejected by the engine, see `Engine::with_collect_rejects`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RejectedTransaction {
+    pub transaction: Transaction,
+    pub error: TransactionProcessingError,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FreezeReason {
+    Chargeback { tx: u32 },
+    Admin,
+}
+
+type FreezeCallback = Box<dyn FnMut(u16, &FreezeReason)>;
+type CommitCallback = Box<dyn FnMut(&Transaction, &Client)>;
+type AmountTransform = Rc<dyn Fn(Decimal) -> Decimal>;
+
+struct Eviction {
+    predicate: Box<dyn Fn(&Client) -> bool>,
+    sink: Box<dyn FnMut(u16, Client)>,
+}
+
+#[derive(Default)]
+pub struct Engine {
+    config: Config,
+    state: State,
+    on_freeze: Option<FreezeCallback>,
+    on_commit: Option<CommitCallback>,
+    eviction: Option<Eviction>,
+    amount_transform: Option<AmountTransform>,
+}
+
+impl std::fmt::Debug for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Engine")
+            .field("config", &self.config)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// When enabled, `BalanceOverflow`/`BalanceUnderflow` stop the whole run
+    /// instead of being skipped like any other rejected transaction.
+    pub fn with_abort_on_overflow(mut self, abort_on_overflow: bool) -> Self {
+        self.config.abort_on_overflow = abort_on_overflow;
+        self
+    }
+
+    /// When enabled, every successfully processed transaction appends a
+    /// `StateTransition` to the audit log. Off by default as it keeps a record
+    /// per transaction in memory.
+    pub fn with_audit(mut self, audit: bool) -> Self {
+        self.config.audit = audit;
+        self
+    }
+
+    /// When enabled, a dispute on a transaction which is already under dispute
+    /// is accepted as a no-op instead of being rejected with `DoubleDispute`.
+    /// Useful for partners resending rows on reconnect.
+    pub fn with_idempotent_disputes(mut self, idempotent_disputes: bool) -> Self {
+        self.config.idempotent_disputes = idempotent_disputes;
+        self
+    }
+
+    /// Caps memory of long runs by evicting clients matching `predicate`
+    /// (e.g. frozen ones) after each of their transactions. Evicted clients are
+    /// handed over to `sink` and removed from the engine together with their
+    /// history.
+    ///
+    /// Eviction is final: the engine remembers only the ids of evicted clients
+    /// and rejects their later transactions with `EvictedClient`, so the
+    /// predicate should match only clients which aren't expected to see any
+    /// further activity.
+    pub fn with_eviction<P, S>(mut self, predicate: P, sink: S) -> Self
+    where
+        P: Fn(&Client) -> bool + 'static,
+        S: FnMut(u16, Client) + 'static,
+    {
+        self.eviction = Some(Eviction {
+            predicate: Box::new(predicate),
+            sink: Box::new(sink),
+        });
+        self
+    }
+
+    /// Applies `transform` to the amount of every deposit and withdrawal
+    /// before processing, e.g. to convert a feed reporting cents into whole
+    /// units. The transform must be deterministic, as it's applied again by
+    /// `dry_run_consistency` and `simulate`.
+    pub fn with_amount_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(Decimal) -> Decimal + 'static,
+    {
+        self.amount_transform = Some(Rc::new(transform));
+        self
+    }
+
+    /// Invoked after every successfully applied transaction with the
+    /// resulting state of its client, e.g. to mirror it to an external store.
+    /// Rejected transactions don't fire it. Replaces any previous callback.
+    pub fn on_commit<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Transaction, &Client) + 'static,
+    {
+        self.on_commit = Some(Box::new(callback));
+    }
+
+    /// Invoked whenever a client becomes frozen, right after the transaction
+    /// (or admin action) which froze it. Replaces any previous callback.
+    pub fn on_freeze<F>(&mut self, callback: F)
+    where
+        F: FnMut(u16, &FreezeReason) + 'static,
+    {
+        self.on_freeze = Some(Box::new(callback));
+    }
+
+    pub fn with_reused_tx_policy(mut self, reused_tx_policy: ReusedTxPolicy) -> Self {
+        self.config.reused_tx_policy = reused_tx_policy;
+        self
+    }
+
+    /// Keeps every rejected transaction along with the reason, see `rejects`.
+    pub fn with_collect_rejects(mut self, collect_rejects: bool) -> Self {
+        self.config.collect_rejects = collect_rejects;
+        self
+    }
+
+    /// Transactions rejected with `AccountFrozen` for frozen clients,
+    /// including replacements under `ReusedTxPolicy::Replace`. Defaults to
+    /// `FreezePolicy::BlockMoneyMovement`.
+    pub fn with_freeze_policy(mut self, policy: FreezePolicy) -> Self {
+        self.config.freeze_policy = policy;
+        self
+    }
+
+    /// Other transactions of closed or evicted clients are rejected
+    /// regardless of the policy.
+    pub fn with_inactive_client_policy(mut self, policy: InactiveClientPolicy) -> Self {
+        self.config.inactive_client_policy = policy;
+        self
+    }
+
+    /// Rejects disputes with `NotYetSettled` until at least
+    /// `settlement_delay` further transactions were applied to the client
+    /// after the disputed one.
+    pub fn with_settlement_delay(mut self, settlement_delay: u64) -> Self {
+        self.config.settlement_delay = settlement_delay;
+        self
+    }
+
+    /// Rejects disputes, resolves and chargebacks with `DisputesDisabled`,
+    /// for feeds consisting only of deposits and withdrawals.
+    pub fn with_disputes_disabled(mut self, disputes_disabled: bool) -> Self {
+        self.config.disputes_disabled = disputes_disabled;
+        self
+    }
+
+    /// Freezes a client only once it accumulated `threshold` chargebacks
+    /// instead of on the first one. Balances are reversed by every chargeback
+    /// regardless.
+    pub fn with_chargeback_freeze_threshold(mut self, threshold: usize) -> Self {
+        self.config.chargeback_freeze_threshold = threshold;
+        self
+    }
+
+    /// Rejects disputes which would raise a client's held funds above
+    /// `max_held` with `HeldLimitExceeded`, leaving the transaction undisputed.
+    pub fn with_max_held_per_client(mut self, max_held: Decimal) -> Self {
+        self.config.max_held_per_client = Some(max_held);
+        self
+    }
+
+    /// Feed integrity check for feeds guaranteeing increasing tx ids per
+    /// client: when enabled, a deposit or withdrawal with a tx id lower than
+    /// the client's last one is rejected with `NonMonotonicTx`.
+    pub fn with_monotonic_tx(mut self, monotonic_tx: bool) -> Self {
+        self.config.monotonic_tx = monotonic_tx;
+        self
+    }
+
+    /// Rejects deposits and authorizations of less than `min_deposit` with
+    /// `BelowMinimumDeposit`. Withdrawals aren't affected.
+    pub fn with_min_deposit(mut self, min_deposit: Decimal) -> Self {
+        self.config.min_deposit = min_deposit;
+        self
+    }
+
+    /// Whether funds of a disputed withdrawal are held or available while
+    /// the dispute lasts, see `WithdrawalDisputePolicy`. Held by default.
+    pub fn with_withdrawal_dispute_policy(mut self, policy: WithdrawalDisputePolicy) -> Self {
+        self.config.withdrawal_dispute_policy = policy;
+        self
+    }
+
+    /// When enabled, a transaction carrying an `idempotency_key` of an
+    /// already applied transaction is accepted as a no-op, so partner
+    /// retries are deduplicated regardless of their tx ids. Keys of rejected
+    /// transactions aren't remembered, so a retry of those is processed.
+    pub fn with_idempotency_keys(mut self, idempotency_keys: bool) -> Self {
+        self.config.idempotency_keys = idempotency_keys;
+        self
+    }
+
+    /// Rounds amounts of deposits, withdrawals and corrections to `scale`
+    /// decimal places before they're applied and recorded, and client
+    /// balances after every applied transaction or correction, so they never
+    /// carry more precision than the output. Uses banker's rounding (half to
+    /// even), e.g. `1.00005` becomes `1.0000` and `1.00015` becomes `1.0002`
+    /// at scale 4. Without it, full `Decimal` precision is kept.
+    pub fn with_internal_scale(mut self, scale: u32) -> Self {
+        self.config.internal_scale = Some(scale);
+        self
+    }
+
+    /// Drops all clients, evictions and the audit log, keeping the
+    /// configuration and callbacks, so the engine can be reused for another
+    /// independent feed.
+    pub fn clear(&mut self) {
+        self.state = State::default();
+    }
+
+    pub fn stats(&self) -> &ProcessingStats {
+        &self.state.stats
+    }
+
+    pub fn audit_log(&self) -> &[StateTransition] {
+        &self.state.audit_log
+    }
+
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.state.clients.get(&id.0)
+    }
+
+    /// Client which recorded the deposit or withdrawal, for support tooling
+    /// tracing a dispute back to its account. If several clients reused the
+    /// id, the first one to record it is returned. Clients added by
+    /// `restore_client` are found only after `rebuild_tx_index`. Evicted
+    /// clients aren't found, their ids are forgotten to bound memory use.
+    pub fn owner_of(&self, tx: TxId) -> Option<ClientId> {
+        let id = *self.state.tx_index.get(&tx.0)?;
+        self.state
+            .clients
+            .get(&id)
+            .filter(|client| client.has_transaction(tx))
+            .map(|_| ClientId(id))
+    }
+
+    /// Repopulates the index behind `owner_of` from the histories of all
+    /// clients, e.g. after restoring them with `restore_client`. Tx ids reused
+    /// by several clients are attributed to the lowest client id.
+    pub fn rebuild_tx_index(&mut self) {
+        let mut tx_index = HashMap::new();
+        for (id, client) in self.iter_clients() {
+            for (tx, _) in client.history() {
+                tx_index.entry(tx).or_insert(id.0);
+            }
+        }
+        self.state.tx_index = tx_index;
+    }
+
+    /// Whether the client is frozen, `None` for unknown clients.
+    pub fn is_frozen(&self, id: ClientId) -> Option<bool> {
+        self.client(id).map(|client| client.is_frozen)
+    }
+
+    /// Fraud heuristic flagging possibly replayed deposits, see
+    /// `Client::duplicate_deposit_amounts`. Empty for unknown clients.
+    pub fn suspicious_duplicate_amounts(&self, id: ClientId, window: u64) -> Vec<(TxId, TxId)> {
+        self.client(id)
+            .map_or_else(Vec::new, |client| client.duplicate_deposit_amounts(window))
+    }
+
+    /// Clients in ascending id order.
+    pub fn iter_clients(&self) -> impl Iterator<Item = (ClientId, &Client)> {
+        self.state
+            .clients
+            .iter()
+            .map(|(id, client)| (ClientId(*id), client))
+    }
+
+    /// State of the client after its first `n` applied transactions, replayed
+    /// into a fresh client from its history. Only deposits and withdrawals
+    /// are kept in the history, so disputes and their outcomes are left out:
+    /// funds disputed later count as available. `None` for unknown clients.
+    /// Fails with the rejection of the first transaction which no longer
+    /// replays, e.g. a withdrawal after a correction lowered an earlier
+    /// deposit.
+    pub fn client_state_at(
+        &self,
+        id: ClientId,
+        n: u64,
+    ) -> Option<Result<ClientSnapshot, TransactionProcessingError>> {
+        let client = self.client(id)?;
+        let mut history = client.history();
+        history.retain(|(_, entry)| entry.sequence <= n);
+        history.sort_by_key(|(_, entry)| entry.sequence);
+        let mut replayed = Client::default();
+        for (tx, entry) in history {
+            let ty = match (&entry.ty, &entry.status) {
+                (BalanceChangeEntryType::Deposit, BalanceChangeEntryStatus::Pending) => {
+                    TransactionType::Authorize
+                }
+                (BalanceChangeEntryType::Deposit, _) => TransactionType::Deposit,
+                (BalanceChangeEntryType::Withdrawal, _) => TransactionType::Withdrawal,
+            };
+            let transaction = Transaction::new(ty, id.0, tx, Some(entry.amount));
+            if let Err(err) = replayed.process_transaction(&transaction) {
+                return Some(Err(err));
+            }
+        }
+        Some(Ok(replayed.snapshot()))
+    }
+
+    /// Rough approximation of the memory used by clients, see
+    /// `Client::estimated_bytes`. Other engine state isn't accounted for.
+    pub fn estimated_bytes(&self) -> usize {
+        self.iter_clients()
+            .map(|(id, client)| std::mem::size_of_val(&id) + client.estimated_bytes())
+            .sum()
+    }
+
+    /// Number of clients currently held by the engine, excluding evicted ones.
+    pub fn client_count(&self) -> usize {
+        self.state.clients.len()
+    }
+
+    /// Frozen clients in ascending id order.
+    pub fn frozen_clients(&self) -> impl Iterator<Item = (ClientId, &Client)> {
+        self.iter_clients().filter(|(_, client)| client.is_frozen)
+    }
+
+    /// Ids of clients changed since the previous call, in ascending order.
+    pub fn take_dirty(&mut self) -> Vec<ClientId> {
+        std::mem::take(&mut self.state.dirty)
+            .into_iter()
+            .filter(|id| self.state.clients.contains_key(id))
+            .map(ClientId)
+            .collect()
+    }
+
+    /// Writes clients changed since the previous `take_dirty` or
+    /// `write_dirty` call, in the `write_clients` format.
+    pub fn write_dirty<W: Write>(&mut self, w: &mut W) -> Result<(), OutputError> {
+        write_header(w)?;
+        for id in self.take_dirty() {
+            write_row(w, u32::from(id.0), &self.state.clients[&id.0].snapshot())?;
+        }
+        Ok(())
+    }
+
+    /// Renders the current state to every sink in a single pass over the
+    /// clients, in ascending id order.
+    pub fn write_all(&self, sinks: &mut [&mut dyn OutputSink]) -> Result<(), OutputError> {
+        for sink in sinks.iter_mut() {
+            sink.begin()?;
+        }
+        for (id, client) in self.iter_clients() {
+            let snapshot = client.snapshot();
+            for sink in sinks.iter_mut() {
+                sink.write_client(id.0, &snapshot)?;
+            }
+        }
+        for sink in sinks.iter_mut() {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds a single account from a copy taken earlier (e.g. by
+    /// `client` or an eviction sink), replacing its current state. Its
+    /// transaction history comes along, so later transactions of the delta
+    /// feed can dispute deposits made before the copy was taken. A
+    /// previously evicted client is accepted again.
+    pub fn restore_client(&mut self, client: Client, id: ClientId) {
+        self.state.evicted.remove(&id.0);
+        if let Some(replaced) = self.state.clients.insert(id.0, client) {
+            forget_tx_owner(&mut self.state.tx_index, id.0, &replaced);
+        }
+        self.state.dirty.insert(id.0);
+    }
+
+    /// Freezes the client on behalf of an operator. Returns `false` if the
+    /// client is unknown.
+    pub fn freeze_client(&mut self, id: ClientId) -> bool {
+        let client = match self.state.clients.get_mut(&id.0) {
+            Some(client) => client,
+            None => return false,
+        };
+        if !client.is_frozen {
+            client.is_frozen = true;
+            self.state.dirty.insert(id.0);
+            self.notify_freeze(id.0, FreezeReason::Admin);
+        }
+        true
+    }
+
+    /// Closes the client's account for offboarding, see `Client::close`.
+    pub fn close_account(&mut self, id: ClientId) -> Result<(), CloseAccountError> {
+        let client = self
+            .state
+            .clients
+            .get_mut(&id.0)
+            .ok_or_else(|| CloseAccountError {
+                blockers: vec![CloseBlocker::UnknownClient],
+            })?;
+        client.close()?;
+        self.state.dirty.insert(id.0);
+        Ok(())
+    }
+
+    /// Ids of clients which appeared in the feed but never made a successful
+    /// deposit. Usually a sign of a broken feed or fraud.
+    pub fn clients_without_deposits(&self) -> Vec<ClientId> {
+        self.iter_clients()
+            .filter(|(_, client)| !client.has_deposits())
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Deep audit: replays the history of every client and reports those
+    /// whose balances differ from what it implies. Any report is a bug
+    /// indicator, except for clients created with `Client::with_balances`,
+    /// which history alone doesn't explain. Clients whose history can't be
+    /// replayed within `Decimal` range are reported without expected balances.
+    pub fn reconcile(&self) -> Vec<ReconciliationError> {
+        self.iter_clients()
+            .filter_map(|(id, client)| {
+                let (expected_available, expected_held) = match client.expected_balances() {
+                    Ok((available, held))
+                        if available == client.available && held == client.held =>
+                    {
+                        return None
+                    }
+                    Ok((available, held)) => (Some(available), Some(held)),
+                    Err(_) => (None, None),
+                };
+                Some(ReconciliationError {
+                    client: id,
+                    expected_available,
+                    expected_held,
+                    available: client.available,
+                    held: client.held,
+                })
+            })
+            .collect()
+    }
+
+    /// Top-level audit of the whole ledger: the available and held funds of
+    /// all clients, plus charged back deposits and minus charged back or
+    /// actively disputed withdrawals, must equal the externally known net
+    /// deposits minus net withdrawals. Disputed withdrawals are subtracted as
+    /// their amount is credited back to available or held funds until the
+    /// dispute ends. Adjustments are taken from client histories, so evicted
+    /// clients and clients created with `Client::with_balances` aren't
+    /// accounted for.
+    pub fn ledger_balance_check(
+        &self,
+        net_deposits: Decimal,
+        net_withdrawals: Decimal,
+    ) -> Result<(), LedgerImbalance> {
+        let expected = net_deposits.checked_sub(net_withdrawals);
+        let actual = self
+            .iter_clients()
+            .try_fold(Decimal::new(0, 0), |actual, (_, client)| {
+                let actual = actual
+                    .checked_add(client.available)?
+                    .checked_add(client.held)?;
+                client.entries().try_fold(actual, |actual, entry| {
+                    match (&entry.ty, &entry.status) {
+                        (
+                            BalanceChangeEntryType::Deposit,
+                            BalanceChangeEntryStatus::ChargedBack,
+                        ) => actual.checked_add(entry.amount),
+                        (
+                            BalanceChangeEntryType::Withdrawal,
+                            BalanceChangeEntryStatus::ChargedBack,
+                        )
+                        | (
+                            BalanceChangeEntryType::Withdrawal,
+                            BalanceChangeEntryStatus::ActiveDispute,
+                        ) => actual.checked_sub(entry.amount),
+                        _ => Some(actual),
+                    }
+                })
+            });
+        match (expected, actual) {
+            (Some(expected), Some(actual)) if expected == actual => Ok(()),
+            _ => Err(LedgerImbalance { expected, actual }),
+        }
+    }
+
+    /// Rejected transactions in processing order, collected only with
+    /// `with_collect_rejects(true)`.
+    pub fn rejects(&self) -> &[RejectedTransaction] {
+        &self.state.rejects
+    }
+
+    /// Writes `rejects` as a JSON array of objects with `line`, `tx`,
+    /// `client`, `type`, `amount`, `code` and `message` keys. Unknown lines
+    /// and missing amounts are `null`, amounts are strings to keep precision.
+    pub fn rejects_json<W: Write>(&self, w: &mut W) -> Result<(), OutputError> {
+        let rejects: Vec<serde_json::Value> = self
+            .state
+            .rejects
+            .iter()
+            .map(|reject| {
+                let transaction = &reject.transaction;
+                serde_json::json!({
+                    "line": transaction.line,
+                    "tx": transaction.tx,
+                    "client": transaction.client,
+                    "type": transaction.ty.name(),
+                    "amount": transaction.amount.map(|amount| amount.to_string()),
+                    "code": reject.error.code(),
+                    "message": reject.error.message(),
+                })
+            })
+            .collect();
+        serde_json::to_writer(&mut *w, &rejects).map_err(std::io::Error::from)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    /// Rejected transactions of every client which had any, by rejection
+    /// reason. Useful to pinpoint accounts generating bad transactions.
+    pub fn client_error_summary(&self) -> HashMap<ClientId, ClientErrorCounts> {
+        self.state
+            .rejections
+            .iter()
+            .map(|(id, counts)| (ClientId(*id), counts.clone()))
+            .collect()
+    }
+
+    /// Number of deposits and withdrawals of all clients in each status,
+    /// a system-wide view of dispute health. Statuses without any entries
+    /// are omitted.
+    pub fn status_distribution(&self) -> HashMap<BalanceChangeEntryStatus, usize> {
+        let mut distribution = HashMap::new();
+        for (_, client) in self.iter_clients() {
+            for entry in client.entries() {
+                *distribution.entry(entry.status.clone()).or_insert(0) += 1;
+            }
+        }
+        distribution
+    }
+
+    pub fn client_list(&self) -> ClientList {
+        self.iter_clients()
+            .map(|(id, client)| (id.0, client.snapshot()))
+            .collect()
+    }
+
+    /// Hash of the balances and frozen flag of every client, in client id
+    /// order. Uses FNV-1a rather than `RandomState`, so equal states hash the
+    /// same across runs and processes, e.g. to compare serial and parallel
+    /// runs without diffing the whole output.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = FnvHasher::default();
+        for (id, client) in self.iter_clients() {
+            id.hash(&mut hasher);
+            client.available.hash(&mut hasher);
+            client.held.hash(&mut hasher);
+            client.is_frozen.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Compares current state against a `baseline` taken earlier with
+    /// `client_list`. Only clients which changed (or appeared) since then are
+    /// reported, in ascending id order.
+    pub fn since_baseline(&self, baseline: &ClientList) -> Vec<ClientDiff> {
+        self.iter_clients()
+            .filter_map(|(id, client)| {
+                let after = client.snapshot();
+                let (before, is_new) = match baseline.get(&id.0) {
+                    Some(before) => (before.clone(), false),
+                    None => (ClientSnapshot::default(), true),
+                };
+                if !is_new && before == after {
+                    return None;
+                }
+                Some(ClientDiff {
+                    client: id,
+                    available: after.available - before.available,
+                    held: after.held - before.held,
+                    is_frozen: if before.is_frozen != after.is_frozen {
+                        Some(after.is_frozen)
+                    } else {
+                        None
+                    },
+                    is_new,
+                })
+            })
+            .collect()
+    }
+
+    /// Pre-flight quality check of a feed. Transactions are applied on top of
+    /// a copy of the current state and every rejection is counted, leaving
+    /// `self` untouched.
+    pub fn dry_run_consistency<I>(&self, transactions: I) -> ConsistencyReport
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut dry_run = self.sandbox();
+        let mut report = ConsistencyReport::default();
+        for transaction in transactions {
+            report.transactions += 1;
+            if let Err(err) = dry_run.process_transaction(transaction) {
+                report.record(&err);
+            }
+        }
+        report
+    }
+
+    /// What-if analysis: applies `transactions` on top of a copy of the
+    /// current state and returns the resulting balances, leaving `self`
+    /// untouched. Rejected transactions are skipped as in `process`.
+    pub fn simulate<I>(&self, transactions: I) -> ClientList
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut simulation = self.sandbox();
+        for transaction in transactions {
+            let _ = simulation.process_transaction(transaction);
+        }
+        simulation.client_list()
+    }
+
+    /// Copy of the configuration, amount transform and state, without
+    /// callbacks and eviction.
+    fn sandbox(&self) -> Engine {
+        Engine {
+            config: self.config.clone(),
+            state: self.state.clone(),
+            amount_transform: self.amount_transform.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// End of day sweep: resolves every dispute opened more than `older_than`
+    /// before `now`, returning the held funds to the client. Disputes are
+    /// dated by the `timestamp` of the dispute transaction; those without one
+    /// are left untouched. Returns `(client, tx)` of swept disputes.
+    pub fn sweep_stale_disputes(
+        &mut self,
+        older_than: Duration,
+        now: SystemTime,
+    ) -> Vec<(ClientId, TxId)> {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let cutoff = now.saturating_sub(older_than.as_secs());
+        let stale: Vec<(ClientId, TxId)> = self
+            .iter_clients()
+            .flat_map(|(id, client)| {
+                client
+                    .disputes_opened_before(cutoff)
+                    .into_iter()
+                    .map(move |tx| (id, TxId(tx)))
+            })
+            .collect();
+        stale
+            .into_iter()
+            .filter(|(client, tx)| {
+                self.process_transaction(Transaction::new(
+                    TransactionType::Resolve,
+                    client.0,
+                    tx.0,
+                    None,
+                ))
+                .is_ok()
+            })
+            .collect()
+    }
+
+    /// Bulk dispute: opens a dispute on every undisputed deposit tagged with
+    /// `group`, holding its funds until resolved or charged back like any
+    /// other dispute. Withdrawals in the group are left untouched, as they
+    /// can't be disputed. Returns `(client, tx)` of disputed deposits.
+    pub fn reverse_group(&mut self, group: u32) -> Vec<(ClientId, TxId)> {
+        let deposits: Vec<(ClientId, TxId)> = self
+            .iter_clients()
+            .flat_map(|(id, client)| {
+                client
+                    .deposits_in_group(group)
+                    .into_iter()
+                    .map(move |tx| (id, TxId(tx)))
+            })
+            .collect();
+        deposits
+            .into_iter()
+            .filter(|(client, tx)| {
+                self.process_transaction(Transaction::new(
+                    TransactionType::Dispute,
+                    client.0,
+                    tx.0,
+                    None,
+                ))
+                .is_ok()
+            })
+            .collect()
+    }
+
+    /// End of day correction pass: sets the amount of each referenced
+    /// deposit or withdrawal, adjusting the client's available funds by the
+    /// difference. Corrections of unknown, disputed or charged back
+    /// transactions are rejected and returned with the reason.
+    pub fn apply_corrections<I>(
+        &mut self,
+        corrections: I,
+    ) -> Vec<(Correction, TransactionProcessingError)>
+    where
+        I: IntoIterator<Item = Correction>,
+    {
+        corrections
+            .into_iter()
+            .filter_map(|correction| {
+                let result = match self.state.clients.get_mut(&correction.client.0) {
+                    Some(client) => {
+                        let amount = round_amount(&self.config, correction.amount);
+                        let result = client.correct_transaction(correction.tx, amount);
+                        if result.is_ok() {
+                            round_balances(&self.config, client);
+                        }
+                        result
+                    }
+                    None => Err(TransactionProcessingError::UnknownTransactionId),
+                };
+                match result {
+                    Ok(()) => {
+                        self.state.dirty.insert(correction.client.0);
+                        None
+                    }
+                    Err(err) => Some((correction, err)),
+                }
+            })
+            .collect()
+    }
+
+    pub fn process_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        self.process_transaction_ref(&transaction)
+    }
+
+    /// Processes all transactions, ignoring partner/client errors. Only an
+    /// overflow under `with_abort_on_overflow(true)` interrupts processing.
+    pub fn process<I>(&mut self, transactions: I) -> Result<(), TransactionProcessingError>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        for transaction in transactions {
+            self.process_leniently(&transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Splits a feed into `num_shards` feeds, for processing by separate
+    /// engines. Each transaction goes to shard `client % num_shards`, where
+    /// disputes, resolves, chargebacks and captures use the client which
+    /// recorded the referenced tx earlier in the feed, if any. Order within
+    /// each shard is preserved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_shards` is zero.
+    pub fn partition_by_client<I>(transactions: I, num_shards: usize) -> Vec<Vec<Transaction>>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        assert!(num_shards > 0, "number of shards must be positive");
+        let mut shards = vec![Vec::new(); num_shards];
+        let mut owners = HashMap::new();
+        for transaction in transactions {
+            let owner = match transaction.ty {
+                TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Authorize => {
+                    owners.entry(transaction.tx).or_insert(transaction.client);
+                    transaction.client
+                }
+                _ => owners
+                    .get(&transaction.tx)
+                    .copied()
+                    .unwrap_or(transaction.client),
+            };
+            shards[usize::from(owner) % num_shards].push(transaction);
+        }
+        shards
+    }
+
+    /// Applies all transactions or none of them: if any is rejected, the
+    /// state from before the batch (balances, histories, indexes, stats) is
+    /// restored and the rejection returned. Callbacks which already fired
+    /// and clients handed to the eviction sink aren't taken back.
+    pub fn apply_atomic<I>(&mut self, transactions: I) -> Result<(), BatchError>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let snapshot = self.state.clone();
+        for (index, transaction) in transactions.into_iter().enumerate() {
+            if let Err(error) = self.process_transaction_ref(&transaction) {
+                self.state = snapshot;
+                return Err(BatchError { index, error });
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `process`, recording the outcome of every transaction and the
+    /// resulting state of its client, in input order. Meant for golden-file
+    /// tests of whole feeds. An overflow under `with_abort_on_overflow(true)`
+    /// ends the trace with the aborting transaction.
+    pub fn process_and_log<I>(&mut self, transactions: I) -> Vec<ProcessedTransaction>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut log = Vec::new();
+        for transaction in transactions {
+            let outcome = self.process_transaction_ref(&transaction);
+            let aborted =
+                matches!(outcome, Err(err) if self.config.abort_on_overflow && err.is_overflow());
+            let client = self
+                .client(ClientId(transaction.client))
+                .map(Client::snapshot);
+            log.push(ProcessedTransaction {
+                transaction,
+                outcome,
+                client,
+            });
+            if aborted {
+                break;
+            }
+        }
+        log
+    }
+
+    /// Same as `process`, but stops before the first transaction matching
+    /// `predicate`, so a scheduler can yield control. Returns the number of
+    /// transactions taken from the feed, including rejected ones, and the
+    /// rest of the feed starting with the matching transaction.
+    pub fn process_until<I, P>(
+        &mut self,
+        transactions: I,
+        mut predicate: P,
+    ) -> Result<(usize, Peekable<I::IntoIter>), TransactionProcessingError>
+    where
+        I: IntoIterator<Item = Transaction>,
+        P: FnMut(&Transaction) -> bool,
+    {
+        let mut transactions = transactions.into_iter().peekable();
+        let mut processed = 0;
+        while let Some(transaction) = transactions.next_if(|transaction| !predicate(transaction)) {
+            self.process_leniently(&transaction)?;
+            processed += 1;
+        }
+        Ok((processed, transactions))
+    }
+
+    /// Same as `process`, but stops right after the first transaction which
+    /// froze its client, so a supervisor can intervene. Returns the number of
+    /// transactions taken from the feed, including the freezing one, the
+    /// frozen client if any, and the rest of the feed.
+    pub fn process_until_freeze<I>(
+        &mut self,
+        transactions: I,
+    ) -> Result<(usize, Option<ClientId>, I::IntoIter), TransactionProcessingError>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut transactions = transactions.into_iter();
+        let mut processed = 0;
+        for transaction in &mut transactions {
+            let id = ClientId(transaction.client);
+            let was_frozen = self.is_frozen(id).unwrap_or(false);
+            self.process_leniently(&transaction)?;
+            processed += 1;
+            if !was_frozen && self.is_frozen(id).unwrap_or(false) {
+                return Ok((processed, Some(id), transactions));
+            }
+        }
+        Ok((processed, None, transactions))
+    }
+
+    /// Same as `process` for a feed split into batches, e.g. by
+    /// `deserialize_batches`. `on_boundary` is called with the engine at
+    /// every batch boundary, so it can checkpoint `client_list` and the like.
+    pub fn process_batches<I, F>(
+        &mut self,
+        items: I,
+        mut on_boundary: F,
+    ) -> Result<(), TransactionProcessingError>
+    where
+        I: IntoIterator<Item = BatchItem>,
+        F: FnMut(&Engine),
+    {
+        for item in items {
+            match item {
+                BatchItem::Transaction(transaction) => self.process_leniently(&transaction)?,
+                BatchItem::Boundary => on_boundary(self),
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `process`, for callers which keep ownership of transactions.
+    pub fn process_slice(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Result<(), TransactionProcessingError> {
+        for transaction in transactions {
+            self.process_leniently(transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Processes a deposit and withdrawal feed grouped by client, writing
+    /// each client in the `write_clients` format as soon as its group ends.
+    /// Written clients are dropped from memory and treated as evicted, so
+    /// later transactions of theirs are rejected with `EvictedClient`. For a
+    /// feed ordered by client id the output matches `write_clients`.
+    /// Rejected transactions are skipped as in `process`.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless disputes are disabled, see `with_disputes_disabled`.
+    pub fn stream_deposit_only<I, W>(
+        &mut self,
+        transactions: I,
+        w: &mut W,
+    ) -> Result<(), OutputError>
+    where
+        I: IntoIterator<Item = Transaction>,
+        W: Write,
+    {
+        assert!(
+            self.config.disputes_disabled,
+            "streaming requires disputes to be disabled"
+        );
+        write_header(w)?;
+        let mut current = None;
+        for transaction in transactions {
+            if let Some(id) = current.filter(|id| *id != transaction.client) {
+                self.write_and_evict(id, w)?;
+            }
+            current = Some(transaction.client);
+            let _ = self.process_transaction_ref(&transaction);
+        }
+        let remaining: Vec<u16> = self.state.clients.keys().copied().collect();
+        for id in remaining {
+            self.write_and_evict(id, w)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Same as `process`, for transactions parsed on another thread. Blocks
+    /// until all senders are dropped. Pairing it with a bounded
+    /// `sync_channel` caps the number of parsed transactions waiting in
+    /// memory.
+    pub fn process_channel(
+        &mut self,
+        rx: Receiver<Transaction>,
+    ) -> Result<(), TransactionProcessingError> {
+        self.process(rx)
+    }
+}
+
+impl Engine {
+    fn process_leniently(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        match self.process_transaction_ref(transaction) {
+            Err(err) if self.config.abort_on_overflow && err.is_overflow() => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_inactive(&self, id: u16) -> bool {
+        self.state.evicted.contains(&id)
+            || self
+                .state
+                .clients
+                .get(&id)
+                .map_or(false, |client| client.is_closed)
+    }
+
+    fn process_transaction_ref(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let result = self.apply_to_state(transaction);
+        if let (Err(error), true) = (result, self.config.collect_rejects) {
+            self.state.rejects.push(RejectedTransaction {
+                transaction: transaction.clone(),
+                error,
+            });
+        }
+        result
+    }
+
+    fn apply_to_state(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionProcessingError> {
+        let transformed;
+        let moves_funds = matches!(
+            transaction.ty,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize
+        );
+        let amount = match (&self.amount_transform, transaction.amount) {
+            (Some(transform), Some(amount)) if moves_funds => {
+                Some(round_amount(&self.config, transform(amount)))
+            }
+            (None, Some(amount)) if moves_funds => Some(round_amount(&self.config, amount)),
+            (_, amount) => amount,
+        };
+        let transaction = if amount != transaction.amount {
+            transformed = Transaction {
+                amount,
+                ..transaction.clone()
+            };
+            &transformed
+        } else {
+            transaction
+        };
+        let (tx, client_id) = (transaction.tx, transaction.client);
+        let idempotency_key = transaction
+            .idempotency_key
+            .as_ref()
+            .filter(|_| self.config.idempotency_keys);
+        if let Some(key) = idempotency_key {
+            if self.state.idempotency_keys.contains(key) {
+                return Ok(());
+            }
+        }
+        // The deposit is validated against a fresh client, which replaces the
+        // inactive one only once it succeeds.
+        let reopening = self.config.inactive_client_policy == InactiveClientPolicy::Reopen
+            && transaction.ty == TransactionType::Deposit
+            && self.is_inactive(client_id);
+        if !reopening && self.state.evicted.contains(&client_id) {
+            let err = TransactionProcessingError::EvictedClient;
+            record_rejection(&mut self.state.rejections, client_id, err);
+            return Err(err);
+        }
+        let monotonic = self.config.monotonic_tx && moves_funds;
+        if monotonic
+            && self
+                .state
+                .last_tx
+                .get(&client_id)
+                .map_or(false, |last| tx < *last)
+        {
+            let err = TransactionProcessingError::NonMonotonicTx;
+            record_rejection(&mut self.state.rejections, client_id, err);
+            return Err(err);
+        }
+        let config = &self.config;
+        let mut reopened = Client::default();
+        let client = if reopening {
+            &mut reopened
+        } else {
+            self.state
+                .clients
+                .entry(client_id)
+                .or_insert_with(Default::default)
+        };
+        let before = client.snapshot();
+        let entry_before = client.entry(TxId(tx)).map(|entry| entry.status.clone());
+        if let Err(err) = apply_transaction(config, client, transaction) {
+            record_rejection(&mut self.state.rejections, client_id, err);
+            return Err(err);
+        }
+        self.state
+            .stats
+            .record(&transaction.ty, entry_before, client.entry(TxId(tx)));
+        round_balances(config, client);
+        let after = client.snapshot();
+        let froze = !before.is_frozen && after.is_frozen;
+        if reopening {
+            self.state.evicted.remove(&client_id);
+            if let Some(closed) = self.state.clients.insert(client_id, reopened) {
+                forget_tx_owner(&mut self.state.tx_index, client_id, &closed);
+            }
+        }
+        self.state.dirty.insert(client_id);
+        if monotonic {
+            self.state.last_tx.insert(client_id, tx);
+        }
+        if moves_funds {
+            self.state.tx_index.entry(tx).or_insert(client_id);
+        }
+        if let Some(key) = idempotency_key {
+            self.state.idempotency_keys.insert(key.clone());
+        }
+        if config.audit {
+            self.state.audit_log.push(StateTransition {
+                tx,
+                client: client_id,
+                before,
+                after,
+                memo: transaction.memo.clone(),
+            });
+        }
+        if let (Some(callback), Some(client)) =
+            (self.on_commit.as_mut(), self.state.clients.get(&client_id))
+        {
+            callback(transaction, client);
+        }
+        if froze {
+            self.notify_freeze(client_id, FreezeReason::Chargeback { tx });
+        }
+        self.evict_if_matching(client_id);
+        Ok(())
+    }
+
+    fn evict_if_matching(&mut self, id: u16) {
+        let eviction = match self.eviction.as_mut() {
+            Some(eviction) => eviction,
+            None => return,
+        };
+        let matches = self
+            .state
+            .clients
+            .get(&id)
+            .map_or(false, |client| (eviction.predicate)(client));
+        if !matches {
+            return;
+        }
+        if let Some(client) = self.state.clients.remove(&id) {
+            self.state.evicted.insert(id);
+            forget_tx_owner(&mut self.state.tx_index, id, &client);
+            (eviction.sink)(id, client);
+        }
+    }
+
+    fn write_and_evict<W: Write>(&mut self, id: u16, w: &mut W) -> Result<(), OutputError> {
+        if let Some(client) = self.state.clients.remove(&id) {
+            self.state.evicted.insert(id);
+            forget_tx_owner(&mut self.state.tx_index, id, &client);
+            write_row(w, u32::from(id), &client.snapshot())?;
+        }
+        Ok(())
+    }
+
+    fn notify_freeze(&mut self, client: u16, reason: FreezeReason) {
+        if let Some(callback) = self.on_freeze.as_mut() {
+            callback(client, &reason);
+        }
+    }
+}
+
+/// 64-bit FNV-1a, see `Engine::state_hash`.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn record_rejection(
+    rejections: &mut HashMap<u16, ClientErrorCounts>,
+    client: u16,
+    err: TransactionProcessingError,
+) {
+    *rejections
+        .entry(client)
+        .or_default()
+        .entry(err)
+        .or_insert(0) += 1;
+}
+
+/// Drops the `State::tx_index` entries of a client leaving the engine, so
+/// the index never outgrows the histories of the clients it serves.
+fn forget_tx_owner(tx_index: &mut HashMap<u32, u16>, id: u16, client: &Client) {
+    for (tx, _) in client.history() {
+        if tx_index.get(&tx) == Some(&id) {
+            tx_index.remove(&tx);
+        }
+    }
+}
+
+fn round_amount(config: &Config, amount: Decimal) -> Decimal {
+    match config.internal_scale {
+        Some(scale) => amount.round_dp(scale),
+        None => amount,
+    }
+}
+
+fn round_balances(config: &Config, client: &mut Client) {
+    if let Some(scale) = config.internal_scale {
+        client.available = client.available.round_dp(scale);
+        client.held = client.held.round_dp(scale);
+    }
+}
+
+fn apply_transaction(
+    config: &Config,
+    client: &mut Client,
+    transaction: &Transaction,
+) -> Result<(), TransactionProcessingError> {
+    if config.idempotent_disputes
+        && matches!(transaction.ty, TransactionType::Dispute)
+        && client.is_disputed(TxId(transaction.tx))
+    {
+        return Ok(());
+    }
+    if config.disputes_disabled
+        && matches!(
+            transaction.ty,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        )
+    {
+        return Err(TransactionProcessingError::DisputesDisabled);
+    }
+    if matches!(transaction.ty, TransactionType::Dispute)
+        && client
+            .transactions_since(TxId(transaction.tx))
+            .map_or(false, |since| since < config.settlement_delay)
+    {
+        return Err(TransactionProcessingError::NotYetSettled);
+    }
+    if let (TransactionType::Dispute, Some(max_held)) =
+        (&transaction.ty, config.max_held_per_client)
+    {
+        // Only disputes that move funds to held count against the cap.
+        let disputed = client.entry(TxId(transaction.tx)).filter(|entry| {
+            entry.status == BalanceChangeEntryStatus::Valid
+                && (entry.ty == BalanceChangeEntryType::Deposit
+                    || config.withdrawal_dispute_policy == WithdrawalDisputePolicy::Held)
+        });
+        if let Some(entry) = disputed {
+            // A sum beyond `Decimal` range exceeds any cap.
+            match client.held.checked_add(entry.amount) {
+                Some(held) if held <= max_held => {}
+                _ => return Err(TransactionProcessingError::HeldLimitExceeded),
+            }
+        }
+    }
+    let deposits = matches!(
+        transaction.ty,
+        TransactionType::Deposit | TransactionType::Authorize
+    );
+    if let (true, Some(amount)) = (deposits, transaction.amount) {
+        if amount < config.min_deposit {
+            return Err(TransactionProcessingError::BelowMinimumDeposit);
+        }
+    }
+    let moves_funds = matches!(
+        transaction.ty,
+        TransactionType::Deposit | TransactionType::Withdrawal
+    );
+    if client.is_frozen && config.freeze_policy.blocks(&transaction.ty) {
+        return Err(TransactionProcessingError::AccountFrozen);
+    }
+    if moves_funds && client.has_transaction(TxId(transaction.tx)) {
+        match config.reused_tx_policy {
+            ReusedTxPolicy::Reject => {}
+            ReusedTxPolicy::Ignore => return Ok(()),
+            ReusedTxPolicy::Replace => return client.replace_transaction(transaction),
+        }
+    }
+    let was_frozen = client.is_frozen;
+    client.process_transaction_with_policy(transaction, config.withdrawal_dispute_policy)?;
+    if let (true, true, Some(line)) = (config.audit, moves_funds, transaction.line) {
+        client.record_line(TxId(transaction.tx), line);
+    }
+    // Only a chargeback freezes, so the history is counted at most once per
+    // chargeback rather than for every transaction.
+    if !was_frozen && client.is_frozen && client.chargebacks() < config.chargeback_freeze_threshold
+    {
+        client.is_frozen = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    fn deposit(client: u16, tx: u32, amount: Decimal) -> Transaction {
+        Transaction::new(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    fn withdrawal(client: u16, tx: u32, amount: Decimal) -> Transaction {
+        Transaction::new(TransactionType::Withdrawal, client, tx, Some(amount))
+    }
+
+    fn dispute(client: u16, tx: u32) -> Transaction {
+        Transaction::new(TransactionType::Dispute, client, tx, None)
+    }
+
+    fn resolve(client: u16, tx: u32) -> Transaction {
+        Transaction::new(TransactionType::Resolve, client, tx, None)
+    }
+
+    fn chargeback(client: u16, tx: u32) -> Transaction {
+        Transaction::new(TransactionType::Chargeback, client, tx, None)
+    }
+
+    mod process {
+        use super::*;
+
+        #[test]
+        fn should_apply_interleaved_clients_in_input_order() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(2, 2, Decimal::new(7, 0)),
+                    dispute(1, 1),
+                    withdrawal(2, 3, Decimal::new(2, 0)),
+                    deposit(1, 4, Decimal::new(1, 0)),
+                    dispute(2, 2),
+                    resolve(1, 1),
+                    deposit(2, 5, Decimal::new(2, 0)),
+                    chargeback(2, 2),
+                ])
+                .unwrap();
+
+            let first = engine.client(ClientId(1)).unwrap();
+            assert_eq!(first.available, Decimal::new(6, 0));
+            assert_eq!(first.held, Decimal::new(0, 0));
+            assert!(!first.is_frozen);
+            let second = engine.client(ClientId(2)).unwrap();
+            assert_eq!(second.available, Decimal::new(0, 0));
+            assert_eq!(second.held, Decimal::new(0, 0));
+            assert!(second.is_frozen);
+        }
+
+        fn overflowing_feed() -> Vec<Transaction> {
+            vec![
+                deposit(1, 1, Decimal::max_value()),
+                deposit(1, 2, Decimal::new(1, 0)),
+                deposit(2, 3, Decimal::new(1, 0)),
+            ]
+        }
+
+        #[test]
+        fn should_skip_overflow_by_default() {
+            let mut engine = Engine::new();
+            engine.process(overflowing_feed()).unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::max_value()
+            );
+            assert_eq!(
+                engine.client(ClientId(2)).unwrap().available,
+                Decimal::new(1, 0)
+            );
+        }
+
+        #[test]
+        fn should_halt_on_overflow_when_enabled() {
+            let mut engine = Engine::new().with_abort_on_overflow(true);
+            let result = engine.process(overflowing_feed());
+            assert_eq!(
+                TransactionProcessingError::BalanceOverflow,
+                result.err().unwrap()
+            );
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::max_value()
+            );
+            assert!(engine.client(ClientId(2)).is_none());
+        }
+
+        #[test]
+        fn should_not_halt_on_other_errors_when_enabled() {
+            let mut engine = Engine::new().with_abort_on_overflow(true);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(1, 0)
+            );
+            assert_eq!(
+                engine.client(ClientId(2)).unwrap().available,
+                Decimal::new(1, 0)
+            );
+        }
+    }
+
+    mod stats {
+        use super::*;
+
+        #[test]
+        fn should_sum_amounts_of_processed_transactions() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    withdrawal(1, 2, Decimal::new(3, 0)),
+                    withdrawal(1, 3, Decimal::new(30, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.stats(),
+                &ProcessingStats {
+                    deposited: Decimal::new(10, 0),
+                    withdrawn: Decimal::new(3, 0),
+                    ..ProcessingStats::default()
+                }
+            );
+        }
+    }
+
+    mod audit {
+        use super::*;
+        use crate::input_types::{deserialize_transactions, TypeEncoding};
+
+        #[test]
+        fn should_record_transition() {
+            let mut engine = Engine::new().with_audit(true);
+            engine
+                .process_transaction(deposit(1, 1, Decimal::new(15, 1)))
+                .unwrap();
+            assert_eq!(
+                engine.audit_log(),
+                &[StateTransition {
+                    tx: 1,
+                    client: 1,
+                    before: ClientSnapshot::default(),
+                    after: ClientSnapshot {
+                        available: Decimal::new(15, 1),
+                        held: Decimal::new(0, 0),
+                        pending: Decimal::new(0, 0),
+                        is_frozen: false,
+                        disputed_count: 0,
+                    },
+                    memo: None,
+                }]
+            );
+        }
+
+        #[test]
+        fn should_record_memo() {
+            let mut engine = Engine::new().with_audit(true);
+            engine
+                .process_transaction(Transaction {
+                    memo: Some("ref-1".to_string()),
+                    ..deposit(1, 1, Decimal::new(15, 1))
+                })
+                .unwrap();
+            assert_eq!(engine.audit_log()[0].memo.as_deref(), Some("ref-1"));
+        }
+
+        #[test]
+        fn should_record_input_line() {
+            let input = "type,client,tx,amount\n\
+                         deposit,1,1,1.0\n\
+                         deposit,1,2,1.0\n\
+                         withdrawal,1,3,0.5\n\
+                         deposit,1,4,2.0\n";
+            let reader = csv::Reader::from_reader(input.as_bytes());
+            let mut engine = Engine::new().with_audit(true);
+            engine
+                .process(deserialize_transactions(reader, TypeEncoding::Name).map(Result::unwrap))
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.entry(TxId(4)).unwrap().line, Some(5));
+            assert_eq!(client.entry(TxId(1)).unwrap().line, Some(2));
+        }
+
+        #[test]
+        fn should_skip_rejected_transactions() {
+            let mut engine = Engine::new().with_audit(true);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(1, 1, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(engine.audit_log().len(), 1);
+        }
+
+        #[test]
+        fn should_be_disabled_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process_transaction(deposit(1, 1, Decimal::new(1, 0)))
+                .unwrap();
+            assert!(engine.audit_log().is_empty());
+        }
+    }
+
+    mod since_baseline {
+        use super::*;
+
+        #[test]
+        fn should_report_deltas() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    deposit(2, 2, Decimal::new(5, 0)),
+                    deposit(3, 3, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            let baseline = engine.client_list();
+            engine
+                .process(vec![
+                    deposit(1, 4, Decimal::new(2, 0)),
+                    dispute(2, 2),
+                    chargeback(2, 2),
+                    deposit(4, 5, Decimal::new(3, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.since_baseline(&baseline),
+                vec![
+                    ClientDiff {
+                        client: ClientId(1),
+                        available: Decimal::new(2, 0),
+                        held: Decimal::new(0, 0),
+                        is_frozen: None,
+                        is_new: false,
+                    },
+                    ClientDiff {
+                        client: ClientId(2),
+                        available: Decimal::new(-5, 0),
+                        held: Decimal::new(0, 0),
+                        is_frozen: Some(true),
+                        is_new: false,
+                    },
+                    ClientDiff {
+                        client: ClientId(4),
+                        available: Decimal::new(3, 0),
+                        held: Decimal::new(0, 0),
+                        is_frozen: None,
+                        is_new: true,
+                    },
+                ]
+            );
+        }
+    }
+
+    mod idempotent_disputes {
+        use super::*;
+
+        fn disputed_engine(engine: Engine) -> Engine {
+            let mut engine = engine;
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(1, 0)), dispute(1, 1)])
+                .unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_reject_repeated_dispute_by_default() {
+            let mut engine = disputed_engine(Engine::new());
+            let result = engine.process_transaction(dispute(1, 1));
+            assert_eq!(
+                TransactionProcessingError::DoubleDispute,
+                result.err().unwrap()
+            );
+            assert_eq!(engine.client(ClientId(1)).unwrap().held, Decimal::new(1, 0));
+        }
+
+        #[test]
+        fn should_accept_repeated_dispute_as_noop() {
+            let mut engine = disputed_engine(Engine::new().with_idempotent_disputes(true));
+            engine.process_transaction(dispute(1, 1)).unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(0, 0));
+            assert_eq!(client.held, Decimal::new(1, 0));
+        }
+
+        #[test]
+        fn should_still_reject_dispute_after_chargeback() {
+            let mut engine = disputed_engine(Engine::new().with_idempotent_disputes(true));
+            engine.process_transaction(chargeback(1, 1)).unwrap();
+            let result = engine.process_transaction(dispute(1, 1));
+            assert_eq!(
+                TransactionProcessingError::AlreadyChargedBack,
+                result.err().unwrap()
+            );
+        }
+    }
+
+    mod clients_without_deposits {
+        use super::*;
+
+        #[test]
+        fn should_report_clients_with_rejected_withdrawal_only() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    withdrawal(2, 2, Decimal::new(1, 0)),
+                    dispute(3, 1),
+                    withdrawal(1, 3, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.clients_without_deposits(),
+                vec![ClientId(2), ClientId(3)]
+            );
+        }
+    }
+
+    mod dry_run_consistency {
+        use super::*;
+
+        #[test]
+        fn should_count_issues() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            let original = engine.state.clone();
+            let report = engine.dry_run_consistency(vec![
+                deposit(1, 2, Decimal::new(1, 0)),
+                deposit(1, 2, Decimal::new(1, 0)),
+                withdrawal(1, 3, Decimal::new(10, 0)),
+                withdrawal(1, 4, Decimal::new(6, 0)),
+                dispute(1, 99),
+                dispute(2, 1),
+                resolve(1, 1),
+                Transaction::new(TransactionType::Deposit, 1, 5, None),
+            ]);
+            assert_eq!(
+                report,
+                ConsistencyReport {
+                    transactions: 8,
+                    unknown_tx_references: 2,
+                    reused_tx_ids: 1,
+                    insufficient_funds: 1,
+                    missing_amounts: 1,
+                    invalid_dispute_transitions: 1,
+                    other: 0,
+                }
+            );
+            assert_eq!(report.rejected(), 6);
+            assert!(!report.is_consistent());
+            assert_eq!(original, engine.state);
+        }
+
+        #[test]
+        fn should_report_clean_feed_as_consistent() {
+            let engine = Engine::new();
+            let report = engine.dry_run_consistency(vec![
+                deposit(1, 1, Decimal::new(5, 0)),
+                dispute(1, 1),
+                resolve(1, 1),
+            ]);
+            assert_eq!(report.transactions, 3);
+            assert!(report.is_consistent());
+        }
+    }
+
+    mod idempotency_keys {
+        use super::*;
+
+        fn keyed(transaction: Transaction, key: &str) -> Transaction {
+            Transaction {
+                idempotency_key: Some(key.to_string()),
+                ..transaction
+            }
+        }
+
+        #[test]
+        fn should_skip_transaction_with_seen_key() {
+            let mut engine = Engine::new().with_idempotency_keys(true);
+            engine
+                .process_transaction(keyed(deposit(1, 1, Decimal::new(5, 0)), "retry-1"))
+                .unwrap();
+            engine
+                .process_transaction(keyed(deposit(1, 2, Decimal::new(5, 0)), "retry-1"))
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(5, 0));
+            assert!(!client.has_transaction(TxId(2)));
+        }
+
+        #[test]
+        fn should_ignore_keys_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    keyed(deposit(1, 1, Decimal::new(5, 0)), "retry-1"),
+                    keyed(deposit(1, 2, Decimal::new(5, 0)), "retry-1"),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(10, 0)
+            );
+        }
+    }
+
+    mod suspicious_duplicate_amounts {
+        use super::*;
+
+        #[test]
+        fn should_flag_identical_deposits_within_window() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    withdrawal(1, 2, Decimal::new(1, 0)),
+                    deposit(1, 3, Decimal::new(50, 1)),
+                    deposit(1, 4, Decimal::new(7, 0)),
+                    deposit(1, 5, Decimal::new(7, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.suspicious_duplicate_amounts(ClientId(1), 2),
+                vec![(TxId(1), TxId(3)), (TxId(4), TxId(5))]
+            );
+            assert_eq!(
+                engine.suspicious_duplicate_amounts(ClientId(1), 1),
+                vec![(TxId(4), TxId(5))]
+            );
+        }
+    }
+
+    mod status_distribution {
+        use super::*;
+
+        #[test]
+        fn should_count_entries_per_status() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(1, 2, Decimal::new(5, 0)),
+                    withdrawal(1, 3, Decimal::new(1, 0)),
+                    dispute(1, 1),
+                    deposit(2, 4, Decimal::new(5, 0)),
+                    deposit(2, 5, Decimal::new(5, 0)),
+                    dispute(2, 4),
+                    chargeback(2, 4),
+                    dispute(2, 5),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.status_distribution(),
+                vec![
+                    (BalanceChangeEntryStatus::Valid, 2),
+                    (BalanceChangeEntryStatus::ActiveDispute, 2),
+                    (BalanceChangeEntryStatus::ChargedBack, 1),
+                ]
+                .into_iter()
+                .collect()
+            );
+        }
+    }
+
+    mod ledger_balance_check {
+        use super::*;
+
+        #[test]
+        fn should_account_for_charged_back_deposits() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    deposit(2, 2, Decimal::new(5, 0)),
+                    withdrawal(1, 3, Decimal::new(3, 0)),
+                    dispute(2, 2),
+                    chargeback(2, 2),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.ledger_balance_check(Decimal::new(15, 0), Decimal::new(3, 0)),
+                Ok(())
+            );
+            assert_eq!(
+                engine.ledger_balance_check(Decimal::new(15, 0), Decimal::new(4, 0)),
+                Err(LedgerImbalance {
+                    expected: Some(Decimal::new(11, 0)),
+                    actual: Some(Decimal::new(12, 0)),
+                })
+            );
+        }
+
+        #[test]
+        fn should_account_for_disputed_withdrawals() {
+            for policy in [
+                WithdrawalDisputePolicy::Held,
+                WithdrawalDisputePolicy::Available,
+            ]
+            .iter()
+            {
+                let mut engine = Engine::new().with_withdrawal_dispute_policy(*policy);
+                engine
+                    .process(vec![
+                        deposit(1, 1, Decimal::new(10, 0)),
+                        withdrawal(1, 2, Decimal::new(4, 0)),
+                        dispute(1, 2),
+                    ])
+                    .unwrap();
+                assert_eq!(
+                    engine.ledger_balance_check(Decimal::new(10, 0), Decimal::new(4, 0)),
+                    Ok(()),
+                    "{:?}",
+                    policy
+                );
+            }
+        }
+
+        #[test]
+        fn should_report_overflowing_sum() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::MAX),
+                    deposit(2, 2, Decimal::MAX),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.ledger_balance_check(Decimal::MAX, Decimal::new(0, 0)),
+                Err(LedgerImbalance {
+                    expected: Some(Decimal::MAX),
+                    actual: None,
+                })
+            );
+        }
+    }
+
+    mod reconcile {
+        use super::*;
+
+        fn engine() -> Engine {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    withdrawal(1, 2, Decimal::new(3, 0)),
+                    deposit(1, 3, Decimal::new(2, 0)),
+                    dispute(1, 3),
+                    deposit(2, 4, Decimal::new(5, 0)),
+                    dispute(2, 4),
+                    chargeback(2, 4),
+                ])
+                .unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_report_nothing_for_healthy_engine() {
+            assert_eq!(engine().reconcile(), vec![]);
+        }
+
+        #[test]
+        fn should_report_corrupted_client() {
+            let mut engine = engine();
+            engine.state.clients.get_mut(&1).unwrap().available += Decimal::new(1, 0);
+            assert_eq!(
+                engine.reconcile(),
+                vec![ReconciliationError {
+                    client: ClientId(1),
+                    expected_available: Some(Decimal::new(7, 0)),
+                    expected_held: Some(Decimal::new(2, 0)),
+                    available: Decimal::new(8, 0),
+                    held: Decimal::new(2, 0),
+                }]
+            );
+        }
+    }
+
+    mod client_error_summary {
+        use super::*;
+
+        #[test]
+        fn should_count_rejections_per_client() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    withdrawal(1, 2, Decimal::new(5, 0)),
+                    withdrawal(1, 3, Decimal::new(5, 0)),
+                    deposit(2, 4, Decimal::new(1, 0)),
+                    dispute(2, 4),
+                    dispute(2, 4),
+                ])
+                .unwrap();
+            let summary = engine.client_error_summary();
+            assert_eq!(summary.len(), 2);
+            assert_eq!(
+                summary[&ClientId(1)],
+                vec![(TransactionProcessingError::NoSufficientFunds, 2)]
+                    .into_iter()
+                    .collect::<ClientErrorCounts>()
+            );
+            assert_eq!(
+                summary[&ClientId(2)],
+                vec![(TransactionProcessingError::DoubleDispute, 1)]
+                    .into_iter()
+                    .collect::<ClientErrorCounts>()
+            );
+        }
+    }
+
+    mod state_hash {
+        use super::*;
+
+        fn feed() -> Vec<Transaction> {
+            vec![
+                deposit(1, 1, Decimal::new(5, 0)),
+                deposit(2, 2, Decimal::new(15, 1)),
+                dispute(2, 2),
+            ]
+        }
+
+        #[test]
+        fn should_hash_equal_states_equally() {
+            let mut first = Engine::new();
+            first.process(feed()).unwrap();
+            let mut second = Engine::new();
+            second.process(feed()).unwrap();
+            assert_eq!(first.state_hash(), second.state_hash());
+
+            second
+                .process(vec![withdrawal(1, 3, Decimal::new(1, 0))])
+                .unwrap();
+            assert_ne!(first.state_hash(), second.state_hash());
+        }
+    }
+
+    mod simulate {
+        use super::*;
+
+        #[test]
+        fn should_apply_feed_to_copy_of_state() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            let original = engine.client_list();
+
+            let simulated = engine.simulate(vec![
+                withdrawal(1, 2, Decimal::new(2, 0)),
+                deposit(2, 3, Decimal::new(1, 0)),
+                withdrawal(2, 4, Decimal::new(9, 0)),
+            ]);
+
+            assert_eq!(engine.client_list(), original);
+            assert_eq!(
+                simulated,
+                vec![
+                    (
+                        1,
+                        ClientSnapshot {
+                            available: Decimal::new(3, 0),
+                            held: Decimal::new(0, 0),
+                            pending: Decimal::new(0, 0),
+                            is_frozen: false,
+                            disputed_count: 0,
+                        }
+                    ),
+                    (
+                        2,
+                        ClientSnapshot {
+                            available: Decimal::new(1, 0),
+                            held: Decimal::new(0, 0),
+                            pending: Decimal::new(0, 0),
+                            is_frozen: false,
+                            disputed_count: 0,
+                        }
+                    ),
+                ]
+                .into_iter()
+                .collect::<ClientList>()
+            );
+        }
+    }
+
+    mod on_commit {
+        use super::*;
+
+        #[test]
+        fn should_fire_only_for_applied_transactions() {
+            let calls = Rc::new(RefCell::new(Vec::new()));
+            let mut engine = Engine::new();
+            let recorded = Rc::clone(&calls);
+            engine.on_commit(move |transaction, client| {
+                recorded
+                    .borrow_mut()
+                    .push((transaction.tx, client.available))
+            });
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    withdrawal(1, 2, Decimal::new(7, 0)),
+                ])
+                .unwrap();
+            assert_eq!(*calls.borrow(), vec![(1, Decimal::new(5, 0))]);
+        }
+    }
+
+    mod on_freeze {
+        use super::*;
+
+        type Calls = Rc<RefCell<Vec<(u16, FreezeReason)>>>;
+
+        fn recording_engine() -> (Engine, Calls) {
+            let calls = Rc::new(RefCell::new(Vec::new()));
+            let mut engine = Engine::new();
+            let recorded = Rc::clone(&calls);
+            engine.on_freeze(move |client, reason| {
+                recorded.borrow_mut().push((client, reason.clone()))
+            });
+            (engine, calls)
+        }
+
+        #[test]
+        fn should_fire_on_chargeback() {
+            let (mut engine, calls) = recording_engine();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(1, 0)),
+                    dispute(2, 2),
+                    chargeback(2, 2),
+                ])
+                .unwrap();
+            assert_eq!(
+                *calls.borrow(),
+                vec![(2, FreezeReason::Chargeback { tx: 2 })]
+            );
+        }
+
+        #[test]
+        fn should_fire_once_per_freeze() {
+            let (mut engine, calls) = recording_engine();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(1, 2, Decimal::new(1, 0)),
+                    dispute(1, 1),
+                    dispute(1, 2),
+                    chargeback(1, 1),
+                    chargeback(1, 2),
+                ])
+                .unwrap();
+            assert_eq!(calls.borrow().len(), 1);
+        }
+
+        #[test]
+        fn should_fire_on_admin_freeze() {
+            let (mut engine, calls) = recording_engine();
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(1, 0))])
+                .unwrap();
+            assert!(engine.freeze_client(ClientId(1)));
+            assert!(!engine.freeze_client(ClientId(2)));
+            assert!(engine.client(ClientId(1)).unwrap().is_frozen);
+            assert_eq!(*calls.borrow(), vec![(1, FreezeReason::Admin)]);
+        }
+    }
+
+    mod dispute_lifecycle {
+        use super::*;
+
+        fn assert_balances(engine: &Engine, available: Decimal, held: Decimal, frozen: bool) {
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, available);
+            assert_eq!(client.held, held);
+            assert_eq!(client.is_frozen, frozen);
+        }
+
+        #[test]
+        fn should_allow_dispute_after_resolve() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    deposit(1, 2, Decimal::new(5, 0)),
+                ])
+                .unwrap();
+            assert_balances(&engine, Decimal::new(15, 0), Decimal::new(0, 0), false);
+
+            engine.process_transaction(dispute(1, 1)).unwrap();
+            assert_balances(&engine, Decimal::new(5, 0), Decimal::new(10, 0), false);
+
+            engine.process_transaction(resolve(1, 1)).unwrap();
+            assert_balances(&engine, Decimal::new(15, 0), Decimal::new(0, 0), false);
+
+            engine.process_transaction(dispute(1, 1)).unwrap();
+            assert_balances(&engine, Decimal::new(5, 0), Decimal::new(10, 0), false);
+
+            engine.process_transaction(chargeback(1, 1)).unwrap();
+            assert_balances(&engine, Decimal::new(5, 0), Decimal::new(0, 0), true);
+
+            assert_eq!(
+                TransactionProcessingError::AlreadyChargedBack,
+                engine.process_transaction(dispute(1, 1)).err().unwrap()
+            );
+            assert_balances(&engine, Decimal::new(5, 0), Decimal::new(0, 0), true);
+        }
+    }
+
+    mod eviction {
+        use super::*;
+
+        #[test]
+        fn should_evict_frozen_clients() {
+            let evicted = Rc::new(RefCell::new(Vec::new()));
+            let sink = Rc::clone(&evicted);
+            let mut engine = Engine::new().with_eviction(
+                |client| client.is_frozen,
+                move |id, client| sink.borrow_mut().push((id, client.snapshot())),
+            );
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(3, 0)),
+                    deposit(2, 3, Decimal::new(2, 0)),
+                    dispute(2, 2),
+                    chargeback(2, 2),
+                ])
+                .unwrap();
+
+            assert!(engine.client(ClientId(1)).is_some());
+            assert!(engine.client(ClientId(2)).is_none());
+            assert_eq!(
+                *evicted.borrow(),
+                vec![(
+                    2,
+                    ClientSnapshot {
+                        available: Decimal::new(2, 0),
+                        held: Decimal::new(0, 0),
+                        pending: Decimal::new(0, 0),
+                        is_frozen: true,
+                        disputed_count: 0,
+                    }
+                )]
+            );
+
+            let result = engine.process_transaction(deposit(2, 4, Decimal::new(1, 0)));
+            assert_eq!(
+                TransactionProcessingError::EvictedClient,
+                result.err().unwrap()
+            );
+            assert!(engine.client(ClientId(2)).is_none());
+        }
+    }
+
+    mod sweep_stale_disputes {
+        use super::*;
+
+        fn dispute_at(client: u16, tx: u32, timestamp: u64) -> Transaction {
+            Transaction {
+                timestamp: Some(timestamp),
+                ..dispute(client, tx)
+            }
+        }
+
+        #[test]
+        fn should_resolve_only_stale_disputes() {
+            let day = 24 * 60 * 60;
+            let now = 10 * day;
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(1, 2, Decimal::new(2, 0)),
+                    deposit(2, 3, Decimal::new(4, 0)),
+                    dispute_at(1, 1, now - 5 * day),
+                    dispute_at(1, 2, now - day),
+                    dispute(2, 3),
+                ])
+                .unwrap();
+
+            let swept = engine.sweep_stale_disputes(
+                Duration::from_secs(3 * day),
+                UNIX_EPOCH + Duration::from_secs(now),
+            );
+
+            assert_eq!(swept, vec![(ClientId(1), TxId(1))]);
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(1, 0));
+            assert_eq!(client.held, Decimal::new(2, 0));
+            assert!(!client.is_disputed(TxId(1)));
+            assert!(client.is_disputed(TxId(2)));
+            assert!(engine.client(ClientId(2)).unwrap().is_disputed(TxId(3)));
+        }
+    }
+
+    mod reverse_group {
+        use super::*;
+
+        fn deposit_in_group(client: u16, tx: u32, amount: Decimal, group: u32) -> Transaction {
+            Transaction {
+                group: Some(group),
+                ..deposit(client, tx, amount)
+            }
+        }
+
+        #[test]
+        fn should_dispute_all_deposits_in_group() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit_in_group(1, 1, Decimal::new(1, 0), 7),
+                    deposit_in_group(2, 2, Decimal::new(2, 0), 7),
+                    deposit_in_group(1, 3, Decimal::new(4, 0), 8),
+                    deposit(1, 4, Decimal::new(8, 0)),
+                ])
+                .unwrap();
+
+            assert_eq!(
+                engine.reverse_group(7),
+                vec![(ClientId(1), TxId(1)), (ClientId(2), TxId(2))]
+            );
+
+            let first = engine.client(ClientId(1)).unwrap();
+            assert_eq!(first.available, Decimal::new(12, 0));
+            assert_eq!(first.held, Decimal::new(1, 0));
+            let second = engine.client(ClientId(2)).unwrap();
+            assert_eq!(second.available, Decimal::new(0, 0));
+            assert_eq!(second.held, Decimal::new(2, 0));
+            assert_eq!(engine.reverse_group(7), vec![]);
+        }
+    }
+
+    mod process_slice {
+        use super::*;
+
+        #[test]
+        fn should_process_borrowed_transactions() {
+            let transactions = vec![
+                deposit(1, 1, Decimal::new(5, 0)),
+                withdrawal(1, 2, Decimal::new(2, 0)),
+                deposit(2, 3, Decimal::new(1, 0)),
+            ];
+            let mut engine = Engine::new();
+            engine.process_slice(&transactions).unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(3, 0)
+            );
+            assert_eq!(
+                engine.client(ClientId(2)).unwrap().available,
+                Decimal::new(1, 0)
+            );
+            assert_eq!(transactions.len(), 3);
+        }
+    }
+
+    mod partition_by_client {
+        use super::*;
+
+        #[test]
+        fn should_route_disputes_with_their_deposits() {
+            let shards = Engine::partition_by_client(
+                vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(1, 0)),
+                    deposit(3, 3, Decimal::new(1, 0)),
+                    dispute(2, 2),
+                    withdrawal(1, 4, Decimal::new(1, 0)),
+                    chargeback(2, 2),
+                ],
+                2,
+            );
+            let txs: Vec<Vec<(u16, u32)>> = shards
+                .iter()
+                .map(|shard| {
+                    shard
+                        .iter()
+                        .map(|transaction| (transaction.client, transaction.tx))
+                        .collect()
+                })
+                .collect();
+            assert_eq!(
+                txs,
+                vec![vec![(2, 2), (2, 2), (2, 2)], vec![(1, 1), (3, 3), (1, 4)]]
+            );
+        }
+
+        #[test]
+        fn should_route_disputes_by_owner_of_the_referenced_tx() {
+            let shards = Engine::partition_by_client(
+                vec![
+                    deposit(2, 1, Decimal::new(1, 0)),
+                    dispute(1, 1),
+                    resolve(3, 1),
+                    dispute(1, 7),
+                ],
+                2,
+            );
+            let txs: Vec<Vec<(u16, u32)>> = shards
+                .iter()
+                .map(|shard| {
+                    shard
+                        .iter()
+                        .map(|transaction| (transaction.client, transaction.tx))
+                        .collect()
+                })
+                .collect();
+            assert_eq!(txs, vec![vec![(2, 1), (1, 1), (3, 1)], vec![(1, 7)]]);
+        }
+    }
+
+    mod apply_atomic {
+        use super::*;
+
+        fn engine() -> Engine {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_roll_back_on_rejection() {
+            let mut engine = engine();
+            let before = engine.state.clone();
+            assert_eq!(
+                engine.apply_atomic(vec![
+                    deposit(2, 2, Decimal::new(3, 0)),
+                    withdrawal(1, 3, Decimal::new(2, 0)),
+                    withdrawal(1, 4, Decimal::new(9, 0)),
+                ]),
+                Err(BatchError {
+                    index: 2,
+                    error: TransactionProcessingError::NoSufficientFunds,
+                })
+            );
+            assert_eq!(engine.state, before);
+            assert_eq!(engine.owner_of(TxId(2)), None);
+        }
+
+        #[test]
+        fn should_apply_whole_batch() {
+            let mut engine = engine();
+            engine
+                .apply_atomic(vec![
+                    deposit(2, 2, Decimal::new(3, 0)),
+                    withdrawal(1, 3, Decimal::new(2, 0)),
+                ])
+                .unwrap();
+            assert_eq!(engine.owner_of(TxId(2)), Some(ClientId(2)));
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(3, 0)
+            );
+        }
+    }
+
+    mod rejects_json {
+        use super::*;
+
+        #[test]
+        fn should_write_rejected_transactions() {
+            let mut engine = Engine::new().with_collect_rejects(true);
+            let mut overdraft = withdrawal(1, 2, Decimal::new(75, 1));
+            overdraft.line = Some(3);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    overdraft,
+                    dispute(2, 9),
+                ])
+                .unwrap();
+            let mut output = Vec::new();
+            engine.rejects_json(&mut output).unwrap();
+            let rejects: serde_json::Value = serde_json::from_slice(&output).unwrap();
+            assert_eq!(
+                rejects,
+                serde_json::json!([
+                    {
+                        "line": 3,
+                        "tx": 2,
+                        "client": 1,
+                        "type": "withdrawal",
+                        "amount": "7.5",
+                        "code": "no_sufficient_funds",
+                        "message": "not enough available funds",
+                    },
+                    {
+                        "line": null,
+                        "tx": 9,
+                        "client": 2,
+                        "type": "dispute",
+                        "amount": null,
+                        "code": "unknown_transaction_id",
+                        "message": "referenced transaction doesn't exist",
+                    },
+                ])
+            );
+        }
+
+        #[test]
+        fn should_collect_nothing_by_default() {
+            let mut engine = Engine::new();
+            engine.process(vec![dispute(1, 1)]).unwrap();
+            assert!(engine.rejects().is_empty());
+        }
+    }
+
+    mod process_and_log {
+        use super::*;
+
+        fn snapshot(
+            available: i64,
+            held: i64,
+            is_frozen: bool,
+            disputed_count: usize,
+        ) -> Option<ClientSnapshot> {
+            Some(ClientSnapshot {
+                available: Decimal::new(available, 0),
+                held: Decimal::new(held, 0),
+                pending: Decimal::new(0, 0),
+                is_frozen,
+                disputed_count,
+            })
+        }
+
+        #[test]
+        fn should_trace_every_transaction() {
+            let feed = vec![
+                deposit(1, 1, Decimal::new(5, 0)),
+                withdrawal(1, 2, Decimal::new(7, 0)),
+                deposit(2, 3, Decimal::new(3, 0)),
+                dispute(2, 3),
+                chargeback(2, 3),
+                resolve(1, 1),
+            ];
+            let mut engine = Engine::new();
+            let log = engine.process_and_log(feed.clone());
+            let expected: Vec<(
+                Result<(), TransactionProcessingError>,
+                Option<ClientSnapshot>,
+            )> = vec![
+                (Ok(()), snapshot(5, 0, false, 0)),
+                (
+                    Err(TransactionProcessingError::NoSufficientFunds),
+                    snapshot(5, 0, false, 0),
+                ),
+                (Ok(()), snapshot(3, 0, false, 0)),
+                (Ok(()), snapshot(0, 3, false, 1)),
+                (Ok(()), snapshot(0, 0, true, 0)),
+                (
+                    Err(TransactionProcessingError::DisputeNotActive),
+                    snapshot(5, 0, false, 0),
+                ),
+            ];
+            let expected: Vec<ProcessedTransaction> = feed
+                .into_iter()
+                .zip(expected)
+                .map(|(transaction, (outcome, client))| ProcessedTransaction {
+                    transaction,
+                    outcome,
+                    client,
+                })
+                .collect();
+            assert_eq!(log, expected);
+        }
+    }
+
+    mod process_until {
+        use super::*;
+
+        #[test]
+        fn should_stop_before_matching_transaction() {
+            let feed = vec![
+                deposit(1, 1, Decimal::new(1, 0)),
+                withdrawal(1, 2, Decimal::new(5, 0)),
+                deposit(5, 3, Decimal::new(2, 0)),
+                deposit(1, 4, Decimal::new(3, 0)),
+            ];
+            let mut engine = Engine::new();
+            let (processed, rest) = engine
+                .process_until(feed, |transaction| transaction.client == 5)
+                .unwrap();
+            assert_eq!(processed, 2);
+            assert!(engine.client(ClientId(5)).is_none());
+
+            engine.process(rest).unwrap();
+            assert_eq!(
+                engine.client(ClientId(5)).unwrap().available,
+                Decimal::new(2, 0)
+            );
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(4, 0)
+            );
+        }
+    }
+
+    mod process_until_freeze {
+        use super::*;
+
+        #[test]
+        fn should_stop_after_freezing_transaction() {
+            let feed = vec![
+                deposit(1, 1, Decimal::new(5, 0)),
+                deposit(2, 2, Decimal::new(3, 0)),
+                dispute(2, 2),
+                chargeback(2, 2),
+                deposit(1, 3, Decimal::new(1, 0)),
+            ];
+            let mut engine = Engine::new();
+            let (processed, frozen, rest) = engine.process_until_freeze(feed).unwrap();
+            assert_eq!((processed, frozen), (4, Some(ClientId(2))));
+            let rest: Vec<u32> = rest.map(|transaction| transaction.tx).collect();
+            assert_eq!(rest, vec![3]);
+        }
+
+        #[test]
+        fn should_process_everything_without_freeze() {
+            let mut engine = Engine::new();
+            let (processed, frozen, mut rest) = engine
+                .process_until_freeze(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            assert_eq!((processed, frozen), (1, None));
+            assert!(rest.next().is_none());
+        }
+    }
+
+    mod process_batches {
+        use super::*;
+        use crate::input_types::{deserialize_batches, TypeEncoding};
+
+        #[test]
+        fn should_call_back_at_batch_boundary() {
+            let input = "type,client,tx,amount\n\
+                         deposit,1,1,5.0\n\
+                         deposit,2,2,1.0\n\
+                         \n\
+                         withdrawal,1,3,2.0\n";
+            let items = deserialize_batches(input.as_bytes(), TypeEncoding::Name)
+                .collect::<csv::Result<Vec<_>>>()
+                .unwrap();
+            let mut checkpoints = Vec::new();
+            let mut engine = Engine::new();
+            engine
+                .process_batches(items, |engine| checkpoints.push(engine.client_list()))
+                .unwrap();
+            assert_eq!(checkpoints.len(), 1);
+            assert_eq!(checkpoints[0][&1].available, Decimal::new(5, 0));
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(3, 0)
+            );
+        }
+    }
+
+    mod settlement_delay {
+        use super::*;
+
+        #[test]
+        fn should_reject_dispute_before_settlement() {
+            let mut engine = Engine::new().with_settlement_delay(2);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(1, 2, Decimal::new(1, 0)),
+                    deposit(2, 3, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.process_transaction(dispute(1, 1)),
+                Err(TransactionProcessingError::NotYetSettled)
+            );
+
+            engine
+                .process_transaction(withdrawal(1, 4, Decimal::new(1, 0)))
+                .unwrap();
+            engine.process_transaction(dispute(1, 1)).unwrap();
+            assert_eq!(engine.client(ClientId(1)).unwrap().held, Decimal::new(5, 0));
+        }
+
+        #[test]
+        fn should_allow_immediate_dispute_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process_transaction(deposit(1, 1, Decimal::new(5, 0)))
+                .unwrap();
+            engine.process_transaction(dispute(1, 1)).unwrap();
+        }
+    }
+
+    mod stream_deposit_only {
+        use super::*;
+        use crate::output::write_clients;
+
+        fn feed() -> Vec<Transaction> {
+            vec![
+                deposit(1, 1, Decimal::new(5, 0)),
+                withdrawal(1, 2, Decimal::new(2, 0)),
+                deposit(2, 3, Decimal::new(15, 1)),
+                withdrawal(2, 4, Decimal::new(3, 0)),
+                deposit(3, 5, Decimal::new(1, 0)),
+            ]
+        }
+
+        #[test]
+        fn should_match_batch_output() {
+            let mut batch = Engine::new();
+            batch.process(feed()).unwrap();
+            let mut expected = Vec::new();
+            write_clients(&batch.client_list(), &mut expected).unwrap();
+
+            let mut engine = Engine::new().with_disputes_disabled(true);
+            let mut output = Vec::new();
+            engine.stream_deposit_only(feed(), &mut output).unwrap();
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                String::from_utf8(expected).unwrap()
+            );
+            assert_eq!(engine.iter_clients().count(), 0);
+        }
+
+        #[test]
+        fn should_reject_disputes() {
+            let mut engine = Engine::new().with_disputes_disabled(true);
+            engine
+                .process_transaction(deposit(1, 1, Decimal::new(5, 0)))
+                .unwrap();
+            assert_eq!(
+                engine.process_transaction(dispute(1, 1)),
+                Err(TransactionProcessingError::DisputesDisabled)
+            );
+        }
+    }
+
+    mod clear {
+        use super::*;
+
+        #[test]
+        fn should_reset_state_and_keep_config() {
+            let mut engine = Engine::new().with_audit(true).with_settlement_delay(3);
+            let config = engine.config.clone();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(2, 2, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+
+            engine.clear();
+
+            assert_eq!(engine.state, State::default());
+            assert_eq!(engine.config, config);
+            engine
+                .process_transaction(deposit(1, 1, Decimal::new(2, 0)))
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(2, 0)
+            );
+            assert_eq!(
+                engine.process_transaction(dispute(1, 1)),
+                Err(TransactionProcessingError::NotYetSettled)
+            );
+        }
+    }
+
+    mod chargeback_freeze_threshold {
+        use super::*;
+
+        #[test]
+        fn should_freeze_on_reaching_threshold() {
+            let mut engine = Engine::new().with_chargeback_freeze_threshold(2);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(1, 2, Decimal::new(3, 0)),
+                    dispute(1, 1),
+                    chargeback(1, 1),
+                ])
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert!(!client.is_frozen);
+            assert_eq!(client.available, Decimal::new(3, 0));
+
+            engine
+                .process(vec![dispute(1, 2), chargeback(1, 2)])
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert!(client.is_frozen);
+            assert_eq!(client.total(), Decimal::new(0, 0));
+        }
+
+        #[test]
+        fn should_freeze_on_first_chargeback_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    dispute(1, 1),
+                    chargeback(1, 1),
+                ])
+                .unwrap();
+            assert!(engine.client(ClientId(1)).unwrap().is_frozen);
+        }
+    }
+
+    mod max_held_per_client {
+        use super::*;
+
+        #[test]
+        fn should_reject_dispute_over_limit() {
+            let mut engine = Engine::new().with_max_held_per_client(Decimal::new(5, 0));
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(3, 0)),
+                    deposit(1, 2, Decimal::new(2, 0)),
+                    deposit(1, 3, Decimal::new(1, 0)),
+                    dispute(1, 1),
+                    dispute(1, 2),
+                ])
+                .unwrap();
+            assert_eq!(engine.client(ClientId(1)).unwrap().held, Decimal::new(5, 0));
+
+            assert_eq!(
+                engine.process_transaction(dispute(1, 3)),
+                Err(TransactionProcessingError::HeldLimitExceeded)
+            );
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.held, Decimal::new(5, 0));
+            assert!(!client.is_disputed(TxId(3)));
+        }
+
+        #[test]
+        fn should_reject_withdrawal_dispute_over_limit_when_held() {
+            let mut engine = Engine::new()
+                .with_withdrawal_dispute_policy(WithdrawalDisputePolicy::Held)
+                .with_max_held_per_client(Decimal::new(5, 0));
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    withdrawal(1, 2, Decimal::new(6, 0)),
+                ])
+                .unwrap();
+
+            assert_eq!(
+                engine.process_transaction(dispute(1, 2)),
+                Err(TransactionProcessingError::HeldLimitExceeded)
+            );
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.held, Decimal::new(0, 0));
+            assert!(!client.is_disputed(TxId(2)));
+        }
+
+        #[test]
+        fn should_not_limit_withdrawal_dispute_credited_to_available() {
+            let mut engine = Engine::new()
+                .with_withdrawal_dispute_policy(WithdrawalDisputePolicy::Available)
+                .with_max_held_per_client(Decimal::new(5, 0));
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    withdrawal(1, 2, Decimal::new(6, 0)),
+                    dispute(1, 2),
+                ])
+                .unwrap();
+            assert!(engine.client(ClientId(1)).unwrap().is_disputed(TxId(2)));
+        }
+
+        #[test]
+        fn should_treat_overflowing_held_as_over_limit() {
+            let mut engine = Engine::new().with_max_held_per_client(Decimal::MAX);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::MAX),
+                    dispute(1, 1),
+                    deposit(1, 2, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+
+            assert_eq!(
+                engine.process_transaction(dispute(1, 2)),
+                Err(TransactionProcessingError::HeldLimitExceeded)
+            );
+        }
+    }
+
+    mod internal_scale {
+        use super::*;
+
+        #[test]
+        fn should_round_balances_to_scale() {
+            let mut engine = Engine::new().with_internal_scale(4);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1_234_567, 6)),
+                    deposit(2, 2, Decimal::new(100_005, 5)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(12_346, 4)
+            );
+            assert_eq!(
+                engine.client(ClientId(2)).unwrap().available,
+                Decimal::new(10_000, 4)
+            );
+        }
+
+        #[test]
+        fn should_round_amount_after_fee() {
+            // 0.125% fee, leaving 1.232956875 of a 1.2345 deposit.
+            let mut engine = Engine::new()
+                .with_internal_scale(4)
+                .with_amount_transform(|amount| amount * Decimal::new(99_875, 5));
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(12_345, 4)), dispute(1, 1)])
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.held, Decimal::new(12_330, 4));
+            assert_eq!(
+                client.entry(TxId(1)).unwrap().amount,
+                Decimal::new(12_330, 4)
+            );
+
+            engine.process_transaction(resolve(1, 1)).unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(12_330, 4));
+            assert_eq!(client.held, Decimal::new(0, 0));
+            assert!(engine.reconcile().is_empty());
+        }
+
+        #[test]
+        fn should_keep_full_precision_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(1_234_567, 6))])
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(1_234_567, 6)
+            );
+        }
+    }
+
+    mod withdrawal_dispute_policy {
+        use super::*;
+
+        fn engine_with_disputed_withdrawal(policy: WithdrawalDisputePolicy) -> Engine {
+            let mut engine = Engine::new().with_withdrawal_dispute_policy(policy);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    withdrawal(1, 2, Decimal::new(4, 0)),
+                    dispute(1, 2),
+                ])
+                .unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_hold_disputed_withdrawal_by_default() {
+            let mut engine = engine_with_disputed_withdrawal(WithdrawalDisputePolicy::default());
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(6, 0));
+            assert_eq!(client.held, Decimal::new(4, 0));
+
+            assert_eq!(
+                engine.process_transaction(withdrawal(1, 3, Decimal::new(8, 0))),
+                Err(TransactionProcessingError::NoSufficientFunds)
+            );
+            engine.process_transaction(chargeback(1, 2)).unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(10, 0));
+            assert_eq!(client.held, Decimal::new(0, 0));
+        }
+
+        #[test]
+        fn should_credit_disputed_withdrawal_when_available() {
+            let mut engine = engine_with_disputed_withdrawal(WithdrawalDisputePolicy::Available);
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(10, 0));
+            assert_eq!(client.held, Decimal::new(0, 0));
+
+            engine
+                .process_transaction(withdrawal(1, 3, Decimal::new(8, 0)))
+                .unwrap();
+            engine.process_transaction(resolve(1, 2)).unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(-2, 0));
+            assert_eq!(client.held, Decimal::new(0, 0));
+        }
+
+        #[test]
+        fn should_reject_dispute_on_withdrawal() {
+            let mut engine =
+                Engine::new().with_withdrawal_dispute_policy(WithdrawalDisputePolicy::Reject);
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    withdrawal(1, 2, Decimal::new(4, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.process_transaction(dispute(1, 2)),
+                Err(TransactionProcessingError::DisputeOnWithdrawal)
+            );
+        }
+    }
+
+    mod monotonic_tx {
+        use super::*;
+
+        #[test]
+        fn should_reject_lower_tx_id() {
+            let mut engine = Engine::new().with_monotonic_tx(true);
+            engine
+                .process(vec![
+                    deposit(1, 5, Decimal::new(5, 0)),
+                    deposit(2, 1, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.process_transaction(deposit(1, 3, Decimal::new(1, 0))),
+                Err(TransactionProcessingError::NonMonotonicTx)
+            );
+            engine
+                .process_transaction(withdrawal(1, 6, Decimal::new(1, 0)))
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(4, 0)
+            );
+        }
+
+        #[test]
+        fn should_accept_any_order_by_default() {
+            let mut engine = Engine::new();
+            engine
+                .process_transaction(deposit(1, 5, Decimal::new(5, 0)))
+                .unwrap();
+            engine
+                .process_transaction(deposit(1, 3, Decimal::new(1, 0)))
+                .unwrap();
+        }
+    }
+
+    mod amount_transform {
+        use super::*;
+
+        #[test]
+        fn should_convert_amounts() {
+            let mut engine =
+                Engine::new().with_amount_transform(|amount| amount / Decimal::new(100, 0));
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1050, 0)),
+                    withdrawal(1, 2, Decimal::new(250, 0)),
+                    dispute(1, 1),
+                ])
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(-25, 1));
+            assert_eq!(client.held, Decimal::new(105, 1));
+        }
+    }
+
+    mod min_deposit {
+        use super::*;
+
+        fn engine() -> Engine {
+            Engine::new().with_min_deposit(Decimal::new(10, 0))
+        }
+
+        #[test]
+        fn should_reject_deposit_below_minimum() {
+            let mut engine = engine();
+            assert_eq!(
+                engine.process_transaction(deposit(1, 1, Decimal::new(999, 2))),
+                Err(TransactionProcessingError::BelowMinimumDeposit)
+            );
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().snapshot(),
+                ClientSnapshot::default()
+            );
+        }
+
+        #[test]
+        fn should_reject_authorization_below_minimum() {
+            let mut engine = engine();
+            let authorize =
+                Transaction::new(TransactionType::Authorize, 1, 1, Some(Decimal::new(999, 2)));
+            assert_eq!(
+                engine.process_transaction(authorize),
+                Err(TransactionProcessingError::BelowMinimumDeposit)
+            );
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().pending,
+                Decimal::new(0, 0)
+            );
+        }
+
+        #[test]
+        fn should_accept_deposit_at_minimum() {
+            let mut engine = engine();
+            engine
+                .process_transaction(deposit(1, 1, Decimal::new(10, 0)))
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(10, 0)
+            );
+        }
+
+        #[test]
+        fn should_accept_deposit_above_minimum_and_any_withdrawal() {
+            let mut engine = engine();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(15, 0)),
+                    withdrawal(1, 2, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(14, 0)
+            );
+        }
+    }
+
+    mod apply_corrections {
+        use super::*;
+
+        fn correction(client: u16, tx: u32, amount: Decimal) -> Correction {
+            Correction {
+                client: ClientId(client),
+                tx: TxId(tx),
+                amount,
+            }
+        }
+
+        #[test]
+        fn should_adjust_balance_by_delta() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    withdrawal(1, 2, Decimal::new(4, 0)),
+                    deposit(1, 3, Decimal::new(1, 0)),
+                    dispute(1, 3),
+                ])
+                .unwrap();
+
+            let rejected = engine.apply_corrections(vec![
+                correction(1, 1, Decimal::new(12, 0)),
+                correction(1, 2, Decimal::new(5, 0)),
+                correction(1, 3, Decimal::new(2, 0)),
+                correction(2, 4, Decimal::new(1, 0)),
+            ]);
+
+            assert_eq!(
+                rejected,
+                vec![
+                    (
+                        correction(1, 3, Decimal::new(2, 0)),
+                        TransactionProcessingError::ReplaceOnDisputedTransaction
+                    ),
+                    (
+                        correction(2, 4, Decimal::new(1, 0)),
+                        TransactionProcessingError::UnknownTransactionId
+                    ),
+                ]
+            );
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(7, 0));
+            assert_eq!(client.held, Decimal::new(1, 0));
+        }
+    }
+
+    mod restore_client {
+        use super::*;
+
+        #[test]
+        fn should_apply_delta_on_top_of_restored_client() {
+            let mut original = Engine::new();
+            original
+                .process(vec![
+                    deposit(1, 1, Decimal::new(10, 0)),
+                    deposit(1, 2, Decimal::new(5, 0)),
+                    deposit(2, 3, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            let snapshot = original.client(ClientId(1)).unwrap().clone();
+
+            let mut engine = Engine::new();
+            engine.restore_client(snapshot, ClientId(1));
+            engine
+                .process(vec![withdrawal(1, 4, Decimal::new(2, 0)), dispute(1, 1)])
+                .unwrap();
+
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(3, 0));
+            assert_eq!(client.held, Decimal::new(10, 0));
+            assert!(client.is_disputed(TxId(1)));
+            assert!(engine.client(ClientId(2)).is_none());
+        }
+    }
+
+    mod owner_of {
+        use super::*;
+
+        #[test]
+        fn should_find_client_of_tx() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(3, 42, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(engine.owner_of(TxId(42)), Some(ClientId(3)));
+            assert_eq!(engine.owner_of(TxId(7)), None);
+        }
+
+        #[test]
+        fn should_find_restored_client_after_rebuild() {
+            let mut source = Engine::new();
+            source
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            let mut engine = Engine::new();
+            for (id, client) in source.iter_clients() {
+                engine.restore_client(client.clone(), id);
+            }
+            assert_eq!(engine.owner_of(TxId(2)), None);
+            engine.rebuild_tx_index();
+            assert_eq!(engine.owner_of(TxId(1)), Some(ClientId(1)));
+            assert_eq!(engine.owner_of(TxId(2)), Some(ClientId(2)));
+        }
+
+        #[test]
+        fn should_forget_txs_of_evicted_client() {
+            let mut engine =
+                Engine::new().with_eviction(|client| client.available.is_zero(), |_, _| {});
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(1, 0)),
+                    withdrawal(1, 3, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(engine.owner_of(TxId(1)), None);
+            assert_eq!(engine.owner_of(TxId(2)), Some(ClientId(2)));
+            assert_eq!(engine.state.tx_index.len(), 1);
+        }
+    }
+
+    mod client_state_at {
+        use super::*;
+
+        #[test]
+        fn should_replay_first_transactions() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(7, 0)),
+                    deposit(1, 3, Decimal::new(2, 0)),
+                    deposit(1, 4, Decimal::new(4, 0)),
+                ])
+                .unwrap();
+            let state = engine.client_state_at(ClientId(1), 2).unwrap().unwrap();
+            assert_eq!(state.available, Decimal::new(3, 0));
+            assert_eq!(
+                engine
+                    .client_state_at(ClientId(1), 3)
+                    .unwrap()
+                    .unwrap()
+                    .available,
+                Decimal::new(7, 0)
+            );
+            assert_eq!(engine.client_state_at(ClientId(3), 1), None);
+        }
+
+        #[test]
+        fn should_fail_when_transaction_no_longer_replays() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    withdrawal(1, 2, Decimal::new(4, 0)),
+                    deposit(1, 3, Decimal::new(10, 0)),
+                ])
+                .unwrap();
+            assert!(engine
+                .apply_corrections(vec![Correction {
+                    client: ClientId(1),
+                    tx: TxId(1),
+                    amount: Decimal::new(1, 0),
+                }])
+                .is_empty());
+            assert_eq!(
+                engine.client_state_at(ClientId(1), 1),
+                Some(Ok(ClientSnapshot {
+                    available: Decimal::new(1, 0),
+                    ..Client::default().snapshot()
+                }))
+            );
+            assert_eq!(
+                engine.client_state_at(ClientId(1), 3),
+                Some(Err(TransactionProcessingError::NoSufficientFunds))
+            );
+        }
+    }
+
+    mod estimated_bytes {
+        use super::*;
+
+        #[test]
+        fn should_grow_with_transactions() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(1, 0))])
+                .unwrap();
+            let before = engine.estimated_bytes();
+            engine
+                .process((2..=50).map(|tx| deposit(1, tx, Decimal::new(1, 0))))
+                .unwrap();
+            assert!(engine.estimated_bytes() > before);
+        }
+    }
+
+    mod client_count {
+        use super::*;
+
+        #[test]
+        fn should_count_distinct_clients() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(1, 0)),
+                    deposit(1, 3, Decimal::new(1, 0)),
+                    deposit(3, 4, Decimal::new(1, 0)),
+                ])
+                .unwrap();
+            assert_eq!(engine.client_count(), 3);
+        }
+    }
+
+    mod freeze_policy {
+        use super::*;
+
+        fn frozen_engine(policy: FreezePolicy) -> Engine {
+            let mut engine = Engine::new().with_freeze_policy(policy);
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            assert!(engine.freeze_client(ClientId(1)));
+            engine
+        }
+
+        #[test]
+        fn should_allow_dispute_when_blocking_money_movement() {
+            let mut engine = frozen_engine(FreezePolicy::BlockMoneyMovement);
+            assert_eq!(
+                engine.process_transaction(deposit(1, 2, Decimal::new(1, 0))),
+                Err(TransactionProcessingError::AccountFrozen)
+            );
+            engine.process_transaction(dispute(1, 1)).unwrap();
+            assert_eq!(engine.client(ClientId(1)).unwrap().held, Decimal::new(5, 0));
+        }
+
+        #[test]
+        fn should_block_money_movement_by_default() {
+            let mut engine = Engine::new().with_reused_tx_policy(ReusedTxPolicy::Replace);
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            assert!(engine.freeze_client(ClientId(1)));
+
+            assert_eq!(
+                engine.process_transaction(withdrawal(1, 2, Decimal::new(1, 0))),
+                Err(TransactionProcessingError::AccountFrozen)
+            );
+            assert_eq!(
+                engine.process_transaction(deposit(1, 1, Decimal::new(7, 0))),
+                Err(TransactionProcessingError::AccountFrozen)
+            );
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(5, 0)
+            );
+        }
+
+        #[test]
+        fn should_reject_dispute_when_blocking_all() {
+            let mut engine = frozen_engine(FreezePolicy::BlockAll);
+            assert_eq!(
+                engine.process_transaction(dispute(1, 1)),
+                Err(TransactionProcessingError::AccountFrozen)
+            );
+            assert_eq!(engine.client(ClientId(1)).unwrap().held, Decimal::new(0, 0));
+        }
+    }
+
+    mod inactive_client_policy {
+        use super::*;
+
+        fn closed_engine(policy: InactiveClientPolicy) -> Engine {
+            let mut engine = Engine::new().with_inactive_client_policy(policy);
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            engine.close_account(ClientId(1)).unwrap();
+            engine
+        }
+
+        #[test]
+        fn should_reject_deposit_to_closed_account_by_default() {
+            let mut engine = closed_engine(InactiveClientPolicy::Reject);
+            assert_eq!(
+                engine.process_transaction(deposit(1, 2, Decimal::new(1, 0))),
+                Err(TransactionProcessingError::AccountClosed)
+            );
+            assert!(engine.client(ClientId(1)).unwrap().is_closed);
+        }
+
+        #[test]
+        fn should_reopen_closed_account_on_deposit() {
+            let mut engine = closed_engine(InactiveClientPolicy::Reopen);
+            assert_eq!(
+                engine.process_transaction(withdrawal(1, 2, Decimal::new(1, 0))),
+                Err(TransactionProcessingError::AccountClosed)
+            );
+            engine
+                .process_transaction(deposit(1, 3, Decimal::new(2, 0)))
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert!(!client.is_closed);
+            assert_eq!(client.available, Decimal::new(2, 0));
+            assert!(!client.has_transaction(TxId(1)));
+        }
+
+        #[test]
+        fn should_keep_closed_account_when_reopening_deposit_is_rejected() {
+            let mut engine = Engine::new()
+                .with_inactive_client_policy(InactiveClientPolicy::Reopen)
+                .with_min_deposit(Decimal::new(2, 0));
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            engine.close_account(ClientId(1)).unwrap();
+
+            assert_eq!(
+                engine.process_transaction(deposit(1, 2, Decimal::new(1, 0))),
+                Err(TransactionProcessingError::BelowMinimumDeposit)
+            );
+            let client = engine.client(ClientId(1)).unwrap();
+            assert!(client.is_closed);
+            assert_eq!(client.available, Decimal::new(5, 0));
+            assert!(client.has_transaction(TxId(1)));
+        }
+
+        #[test]
+        fn should_forget_tx_owners_of_reopened_account() {
+            let mut engine = closed_engine(InactiveClientPolicy::Reopen);
+            engine
+                .process(vec![
+                    deposit(1, 2, Decimal::new(2, 0)),
+                    deposit(2, 1, Decimal::new(3, 0)),
+                ])
+                .unwrap();
+            assert_eq!(engine.owner_of(TxId(1)), Some(ClientId(2)));
+            assert_eq!(engine.owner_of(TxId(2)), Some(ClientId(1)));
+        }
+    }
+
+    mod frozen_clients {
+        use super::*;
+
+        #[test]
+        fn should_yield_only_frozen_clients() {
+            let mut engine = Engine::new();
+            engine
+                .process((1..=3).map(|id| deposit(id, u32::from(id), Decimal::new(1, 0))))
+                .unwrap();
+            assert!(engine.freeze_client(ClientId(3)));
+            assert!(engine.freeze_client(ClientId(1)));
+            let frozen: Vec<ClientId> = engine.frozen_clients().map(|(id, _)| id).collect();
+            assert_eq!(frozen, vec![ClientId(1), ClientId(3)]);
+        }
+    }
+
+    mod close_account {
+        use super::*;
+
+        #[test]
+        fn should_close_clean_account() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    dispute(1, 1),
+                    resolve(1, 1),
+                ])
+                .unwrap();
+            assert_eq!(engine.close_account(ClientId(1)), Ok(()));
+            assert!(engine.client(ClientId(1)).unwrap().is_closed);
+            assert_eq!(
+                engine.process_transaction(deposit(1, 2, Decimal::new(1, 0))),
+                Err(TransactionProcessingError::AccountClosed)
+            );
+        }
+
+        #[test]
+        fn should_list_blockers() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(1, 2, Decimal::new(3, 0)),
+                    dispute(1, 2),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.close_account(ClientId(1)),
+                Err(CloseAccountError {
+                    blockers: vec![
+                        CloseBlocker::ActiveDispute { tx: 2 },
+                        CloseBlocker::HeldFunds(Decimal::new(3, 0)),
+                    ]
+                })
+            );
+            assert!(!engine.client(ClientId(1)).unwrap().is_closed);
+            assert_eq!(
+                engine.close_account(ClientId(2)),
+                Err(CloseAccountError {
+                    blockers: vec![CloseBlocker::UnknownClient]
+                })
+            );
+        }
+
+        #[test]
+        fn should_reject_replacement_and_correction_of_closed_account() {
+            let mut engine = Engine::new().with_reused_tx_policy(ReusedTxPolicy::Replace);
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(5, 0))])
+                .unwrap();
+            engine.close_account(ClientId(1)).unwrap();
+
+            assert_eq!(
+                engine.process_transaction(deposit(1, 1, Decimal::new(7, 0))),
+                Err(TransactionProcessingError::AccountClosed)
+            );
+            let rejected = engine.apply_corrections(vec![Correction {
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Decimal::new(7, 0),
+            }]);
+            assert_eq!(rejected.len(), 1);
+            assert_eq!(rejected[0].1, TransactionProcessingError::AccountClosed);
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(5, 0));
+            assert_eq!(client.entry(TxId(1)).unwrap().amount, Decimal::new(5, 0));
+        }
+    }
+
+    mod is_frozen {
+        use super::*;
+
+        #[test]
+        fn should_report_frozen_client() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(2, 2, Decimal::new(5, 0)),
+                    dispute(1, 1),
+                    chargeback(1, 1),
+                ])
+                .unwrap();
+            assert_eq!(engine.is_frozen(ClientId(1)), Some(true));
+            assert_eq!(engine.is_frozen(ClientId(2)), Some(false));
+            assert_eq!(engine.is_frozen(ClientId(3)), None);
+        }
+    }
+
+    mod write_all {
+        use super::*;
+        use crate::output::{CsvSink, JsonLinesSink};
+
+        #[test]
+        fn should_write_every_sink() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(15, 1)),
+                    deposit(2, 2, Decimal::new(2, 0)),
+                    dispute(2, 2),
+                ])
+                .unwrap();
+            let (mut csv, mut json) = (Vec::new(), Vec::new());
+            engine
+                .write_all(&mut [
+                    &mut CsvSink::new(&mut csv),
+                    &mut JsonLinesSink::new(&mut json),
+                ])
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(csv).unwrap(),
+                "client,available,held,total,locked\n\
+                 1,1.5,0,1.5,false\n\
+                 2,0,2,2,false\n"
+            );
+            assert_eq!(
+                String::from_utf8(json).unwrap(),
+                "{\"available\":\"1.5\",\"client\":1,\"held\":\"0\",\"locked\":false,\"total\":\"1.5\"}\n\
+                 {\"available\":\"0\",\"client\":2,\"held\":\"2\",\"locked\":false,\"total\":\"2\"}\n"
+            );
+        }
+    }
+
+    mod write_dirty {
+        use super::*;
+
+        #[test]
+        fn should_write_only_clients_changed_since_last_flush() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(1, 0)),
+                    deposit(2, 2, Decimal::new(2, 0)),
+                ])
+                .unwrap();
+            let mut first = Vec::new();
+            engine.write_dirty(&mut first).unwrap();
+            assert_eq!(
+                String::from_utf8(first).unwrap(),
+                "client,available,held,total,locked\n\
+                 1,1,0,1,false\n\
+                 2,2,0,2,false\n"
+            );
+
+            engine
+                .process(vec![deposit(2, 3, Decimal::new(1, 0))])
+                .unwrap();
+            let mut second = Vec::new();
+            engine.write_dirty(&mut second).unwrap();
+            assert_eq!(
+                String::from_utf8(second).unwrap(),
+                "client,available,held,total,locked\n\
+                 2,3,0,3,false\n"
+            );
+            assert!(engine.take_dirty().is_empty());
+        }
+    }
+
+    mod process_channel {
+        use std::{sync::mpsc::sync_channel, thread};
+
+        use super::*;
+
+        #[test]
+        fn should_process_all_sent_transactions() {
+            let (tx, rx) = sync_channel(2);
+            let producer = thread::spawn(move || {
+                for id in 1..=100 {
+                    tx.send(deposit(1, id, Decimal::new(1, 0))).unwrap();
+                }
+            });
+            let mut engine = Engine::new();
+            engine.process_channel(rx).unwrap();
+            producer.join().unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(100, 0)
+            );
+        }
+    }
+
+    mod duplicated_transactions {
+        use super::*;
+
+        #[test]
+        fn should_not_double_apply_within_batch() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(1, 2, Decimal::new(3, 0)),
+                    dispute(1, 1),
+                    dispute(1, 1),
+                    dispute(1, 2),
+                    resolve(1, 2),
+                    resolve(1, 2),
+                ])
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(3, 0));
+            assert_eq!(client.held, Decimal::new(5, 0));
+            assert_eq!(client.total(), Decimal::new(8, 0));
+        }
+
+        #[test]
+        fn should_not_double_apply_chargeback() {
+            let mut engine = Engine::new();
+            engine
+                .process(vec![
+                    deposit(1, 1, Decimal::new(5, 0)),
+                    deposit(1, 2, Decimal::new(3, 0)),
+                    dispute(1, 1),
+                    chargeback(1, 1),
+                    chargeback(1, 1),
+                ])
+                .unwrap();
+            let client = engine.client(ClientId(1)).unwrap();
+            assert_eq!(client.available, Decimal::new(3, 0));
+            assert_eq!(client.held, Decimal::new(0, 0));
+        }
+    }
+
+    mod reused_tx_policy {
+        use super::*;
+
+        type TransactionProcessingResult = Result<(), TransactionProcessingError>;
+
+        fn process_with_policy(policy: ReusedTxPolicy) -> (Engine, TransactionProcessingResult) {
+            let mut engine = Engine::new().with_reused_tx_policy(policy);
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(10, 0))])
+                .unwrap();
+            let result = engine.process_transaction(deposit(1, 1, Decimal::new(7, 0)));
+            (engine, result)
+        }
+
+        #[test]
+        fn should_reject_by_default() {
+            let (engine, result) = process_with_policy(ReusedTxPolicy::default());
+            assert_eq!(
+                TransactionProcessingError::ReusedTransactionId,
+                result.err().unwrap()
+            );
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(10, 0)
+            );
+        }
+
+        #[test]
+        fn should_ignore() {
+            let (engine, result) = process_with_policy(ReusedTxPolicy::Ignore);
+            result.unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(10, 0)
+            );
+        }
+
+        #[test]
+        fn should_replace() {
+            let (engine, result) = process_with_policy(ReusedTxPolicy::Replace);
+            result.unwrap();
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().available,
+                Decimal::new(7, 0)
+            );
+        }
+
+        #[test]
+        fn should_not_replace_disputed_entry() {
+            let mut engine = Engine::new().with_reused_tx_policy(ReusedTxPolicy::Replace);
+            engine
+                .process(vec![deposit(1, 1, Decimal::new(10, 0)), dispute(1, 1)])
+                .unwrap();
+            let result = engine.process_transaction(deposit(1, 1, Decimal::new(7, 0)));
+            assert_eq!(
+                TransactionProcessingError::ReplaceOnDisputedTransaction,
+                result.err().unwrap()
+            );
+            assert_eq!(
+                engine.client(ClientId(1)).unwrap().held,
+                Decimal::new(10, 0)
+            );
+        }
+    }
+}
@@ -1,15 +1,118 @@
+use rust_decimal::Decimal;
 use thiserror::Error;
 
+use crate::ids::ClientId;
+
 #[non_exhaustive]
-#[derive(Debug, Error, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq, Hash)]
 pub enum TransactionProcessingError {
     ReusedTransactionId,
     AmountNotSpecified,
+    NonPositiveAmount,
     NoSufficientFunds,
     UnknownTransactionId,
     DoubleDispute,
     DisputeNotActive,
+    AlreadyChargedBack,
+    ReplaceOnDisputedTransaction,
     DisputeOnWithdrawal,
+    BalanceOverflow,
+    BalanceUnderflow,
+    EvictedClient,
+    NotYetSettled,
+    DisputesDisabled,
+    HeldLimitExceeded,
+    /// Chargeback of more than the held funds, which means a corrupted state.
+    HeldUnderflow,
+    BelowMinimumDeposit,
+    NonMonotonicTx,
+    AccountClosed,
+    /// Capture of a transaction which isn't an authorized deposit awaiting
+    /// capture.
+    NotPending,
+    /// Dispute of an authorized deposit which wasn't captured yet.
+    NotCaptured,
+    AccountFrozen,
+}
+
+impl TransactionProcessingError {
+    pub fn is_overflow(&self) -> bool {
+        matches!(
+            self,
+            TransactionProcessingError::BalanceOverflow
+                | TransactionProcessingError::BalanceUnderflow
+        )
+    }
+
+    /// Stable snake_case identifier, for machine consumers.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionProcessingError::ReusedTransactionId => "reused_transaction_id",
+            TransactionProcessingError::AmountNotSpecified => "amount_not_specified",
+            TransactionProcessingError::NonPositiveAmount => "non_positive_amount",
+            TransactionProcessingError::NoSufficientFunds => "no_sufficient_funds",
+            TransactionProcessingError::UnknownTransactionId => "unknown_transaction_id",
+            TransactionProcessingError::DoubleDispute => "double_dispute",
+            TransactionProcessingError::DisputeNotActive => "dispute_not_active",
+            TransactionProcessingError::AlreadyChargedBack => "already_charged_back",
+            TransactionProcessingError::ReplaceOnDisputedTransaction => {
+                "replace_on_disputed_transaction"
+            }
+            TransactionProcessingError::DisputeOnWithdrawal => "dispute_on_withdrawal",
+            TransactionProcessingError::BalanceOverflow => "balance_overflow",
+            TransactionProcessingError::BalanceUnderflow => "balance_underflow",
+            TransactionProcessingError::EvictedClient => "evicted_client",
+            TransactionProcessingError::NotYetSettled => "not_yet_settled",
+            TransactionProcessingError::DisputesDisabled => "disputes_disabled",
+            TransactionProcessingError::HeldLimitExceeded => "held_limit_exceeded",
+            TransactionProcessingError::HeldUnderflow => "held_underflow",
+            TransactionProcessingError::BelowMinimumDeposit => "below_minimum_deposit",
+            TransactionProcessingError::NonMonotonicTx => "non_monotonic_tx",
+            TransactionProcessingError::AccountClosed => "account_closed",
+            TransactionProcessingError::NotPending => "not_pending",
+            TransactionProcessingError::NotCaptured => "not_captured",
+            TransactionProcessingError::AccountFrozen => "account_frozen",
+        }
+    }
+
+    /// Short human readable description.
+    pub fn message(&self) -> &'static str {
+        match self {
+            TransactionProcessingError::ReusedTransactionId => "transaction id was already used",
+            TransactionProcessingError::AmountNotSpecified => "amount is missing",
+            TransactionProcessingError::NonPositiveAmount => "amount must be positive",
+            TransactionProcessingError::NoSufficientFunds => "not enough available funds",
+            TransactionProcessingError::UnknownTransactionId => {
+                "referenced transaction doesn't exist"
+            }
+            TransactionProcessingError::DoubleDispute => "transaction is already disputed",
+            TransactionProcessingError::DisputeNotActive => "transaction isn't disputed",
+            TransactionProcessingError::AlreadyChargedBack => {
+                "transaction was already charged back"
+            }
+            TransactionProcessingError::ReplaceOnDisputedTransaction => {
+                "disputed transaction can't be replaced"
+            }
+            TransactionProcessingError::DisputeOnWithdrawal => "withdrawals can't be disputed",
+            TransactionProcessingError::BalanceOverflow => "balance would overflow",
+            TransactionProcessingError::BalanceUnderflow => "balance would underflow",
+            TransactionProcessingError::EvictedClient => "client was evicted",
+            TransactionProcessingError::NotYetSettled => "transaction isn't settled yet",
+            TransactionProcessingError::DisputesDisabled => "disputes are disabled",
+            TransactionProcessingError::HeldLimitExceeded => "held funds limit would be exceeded",
+            TransactionProcessingError::HeldUnderflow => {
+                "held funds are lower than the charged back amount"
+            }
+            TransactionProcessingError::BelowMinimumDeposit => "deposit is below the minimum",
+            TransactionProcessingError::NonMonotonicTx => {
+                "transaction id is lower than a previous one"
+            }
+            TransactionProcessingError::AccountClosed => "account is closed",
+            TransactionProcessingError::NotPending => "transaction isn't awaiting capture",
+            TransactionProcessingError::NotCaptured => "transaction wasn't captured yet",
+            TransactionProcessingError::AccountFrozen => "account is frozen",
+        }
+    }
 }
 
 impl std::fmt::Display for TransactionProcessingError {
@@ -17,3 +120,192 @@ impl std::fmt::Display for TransactionProcessingError {
         write!(f, "{:?}", self)
     }
 }
+
+#[derive(Debug, Error)]
+pub enum OutputError {
+    Io(std::io::Error),
+    ValueTooWide {
+        column: &'static str,
+        value: String,
+        width: usize,
+    },
+}
+
+impl std::fmt::Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<std::io::Error> for OutputError {
+    fn from(err: std::io::Error) -> Self {
+        OutputError::Io(err)
+    }
+}
+
+/// Broken internal invariant of a client, see `Client::verify_held_consistency`.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvariantViolation {
+    HeldMismatch { expected: Decimal, actual: Decimal },
+}
+
+impl std::fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Client whose balances don't match its transaction history, see
+/// `Engine::reconcile`.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub struct ReconciliationError {
+    pub client: ClientId,
+    /// Balances implied by the history, `None` if replaying it overflows.
+    pub expected_available: Option<Decimal>,
+    pub expected_held: Option<Decimal>,
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+impl std::fmt::Display for ReconciliationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Reason an account can't be closed, see `Engine::close_account`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CloseBlocker {
+    UnknownClient,
+    Frozen,
+    ActiveDispute { tx: u32 },
+    HeldFunds(Decimal),
+    PendingFunds(Decimal),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub struct CloseAccountError {
+    pub blockers: Vec<CloseBlocker>,
+}
+
+impl std::fmt::Display for CloseAccountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Mismatch between the balances of all clients and the net flow of funds,
+/// see `Engine::ledger_balance_check`.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub struct LedgerImbalance {
+    /// Net deposits minus net withdrawals, `None` if out of `Decimal` range.
+    pub expected: Option<Decimal>,
+    /// Balances of all clients, adjusted by the charged back and disputed
+    /// amounts, `None` if out of `Decimal` range.
+    pub actual: Option<Decimal>,
+}
+
+impl std::fmt::Display for LedgerImbalance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Rejected transaction which rolled back a batch, see
+/// `Engine::apply_atomic`.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub struct BatchError {
+    /// Position of the transaction in the batch.
+    pub index: usize,
+    pub error: TransactionProcessingError,
+}
+
+impl std::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Failure of `Engine::process_file_async`.
+#[cfg(feature = "async")]
+#[derive(Debug, Error)]
+pub enum FileProcessingError {
+    Io(std::io::Error),
+    /// The header row couldn't be read.
+    Csv(csv_async::Error),
+    /// Row with an unknown `type` in strict mode, see
+    /// `TransactionReaderBuilder::strict_types`.
+    Input(InputError),
+    Processing(TransactionProcessingError),
+}
+
+#[cfg(feature = "async")]
+impl std::fmt::Display for FileProcessingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<std::io::Error> for FileProcessingError {
+    fn from(err: std::io::Error) -> Self {
+        FileProcessingError::Io(err)
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<csv_async::Error> for FileProcessingError {
+    fn from(err: csv_async::Error) -> Self {
+        FileProcessingError::Csv(err)
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<InputError> for FileProcessingError {
+    fn from(err: InputError) -> Self {
+        FileProcessingError::Input(err)
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<TransactionProcessingError> for FileProcessingError {
+    fn from(err: TransactionProcessingError) -> Self {
+        FileProcessingError::Processing(err)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum InputError {
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    /// Amount which can't be represented as a `Decimal`.
+    InvalidAmount,
+    /// Malformed value of a column of a headerless row.
+    InvalidField {
+        column: &'static str,
+    },
+    /// Value of the `type` column which isn't a known transaction type, see
+    /// `TransactionReaderBuilder::strict_types`.
+    UnknownType {
+        line: u64,
+        value: String,
+    },
+}
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<serde_json::Error> for InputError {
+    fn from(err: serde_json::Error) -> Self {
+        InputError::Json(err)
+    }
+}
+
+impl From<csv::Error> for InputError {
+    fn from(err: csv::Error) -> Self {
+        InputError::Csv(err)
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+/// Id of a client, as in the `client` column. Used by the engine API, while
+/// `Transaction` and the output formats keep the raw CSV column values.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct ClientId(pub u16);
+
+/// Id of a deposit or withdrawal, as in the `tx` column.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct TxId(pub u32);
+
+impl From<u16> for ClientId {
+    fn from(id: u16) -> Self {
+        ClientId(id)
+    }
+}
+
+impl From<ClientId> for u16 {
+    fn from(id: ClientId) -> Self {
+        id.0
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u32> for TxId {
+    fn from(id: u32) -> Self {
+        TxId(id)
+    }
+}
+
+impl From<TxId> for u32 {
+    fn from(id: TxId) -> Self {
+        id.0
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_convert_client_id() {
+        let id = ClientId::from(7);
+        assert_eq!(id, ClientId(7));
+        assert_eq!(u16::from(id), 7);
+        assert_eq!(id.to_string(), "7");
+    }
+
+    #[test]
+    fn should_convert_tx_id() {
+        let id = TxId::from(70_000);
+        assert_eq!(id, TxId(70_000));
+        assert_eq!(u32::from(id), 70_000);
+        assert_eq!(id.to_string(), "70000");
+        assert!(TxId(1) < TxId(2));
+    }
+
+    #[test]
+    fn should_deserialize_from_plain_number() {
+        let id: ClientId = serde_json::from_str("7").unwrap();
+        assert_eq!(id, ClientId(7));
+        let id: TxId = serde_json::from_str("70000").unwrap();
+        assert_eq!(id, TxId(70_000));
+    }
+}
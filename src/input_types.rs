@@ -1,7 +1,20 @@
-use rust_decimal::Decimal;
-use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    io::{BufRead, Cursor, Read},
+};
 
-#[derive(Debug, Deserialize)]
+use csv::StringRecord;
+
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    errors::InputError,
+    ids::{ClientId, TxId},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum TransactionType {
     Deposit,
@@ -9,12 +22,947 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Provisional deposit, credited to pending funds until captured.
+    Authorize,
+    /// Confirmation of an authorized deposit, moving its amount from pending
+    /// to available funds.
+    Capture,
+}
+
+impl TransactionType {
+    /// Parses a legacy integer code: 1=deposit, 2=withdrawal, 3=dispute,
+    /// 4=resolve, 5=chargeback, 6=authorize, 7=capture.
+    pub fn from_code(code: &str) -> Option<TransactionType> {
+        match code {
+            "1" => Some(TransactionType::Deposit),
+            "2" => Some(TransactionType::Withdrawal),
+            "3" => Some(TransactionType::Dispute),
+            "4" => Some(TransactionType::Resolve),
+            "5" => Some(TransactionType::Chargeback),
+            "6" => Some(TransactionType::Authorize),
+            "7" => Some(TransactionType::Capture),
+            _ => None,
+        }
+    }
+
+    /// Name as used in the `type` column, e.g. `deposit`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+        }
+    }
+
+    /// Parses a name as used in the `type` column, e.g. `deposit`.
+    pub fn from_name(name: &str) -> Option<TransactionType> {
+        match name {
+            "deposit" => Some(TransactionType::Deposit),
+            "withdrawal" => Some(TransactionType::Withdrawal),
+            "dispute" => Some(TransactionType::Dispute),
+            "resolve" => Some(TransactionType::Resolve),
+            "chargeback" => Some(TransactionType::Chargeback),
+            "authorize" => Some(TransactionType::Authorize),
+            "capture" => Some(TransactionType::Capture),
+            _ => None,
+        }
+    }
 }
-#[derive(Debug, Deserialize)]
+
+/// How the `type` column of the input is encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeEncoding {
+    /// `deposit`, `withdrawal`, ...
+    Name,
+    /// Legacy integer codes, see `TransactionType::from_code`.
+    Code,
+}
+
+impl Default for TypeEncoding {
+    fn default() -> Self {
+        TypeEncoding::Name
+    }
+}
+
+impl TypeEncoding {
+    fn parse(self, ty: &str) -> Option<TransactionType> {
+        match self {
+            TypeEncoding::Name => TransactionType::from_name(ty),
+            TypeEncoding::Code => TransactionType::from_code(ty),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub ty: TransactionType,
     pub client: u16,
     pub tx: u32,
+    /// See `parse_amount` for the accepted format.
+    #[serde(default, deserialize_with = "deserialize_amount")]
     pub amount: Option<Decimal>,
+    /// Unix timestamp (seconds) of the transaction, if the feed provides one.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Partner assigned id of a group of related transactions, see
+    /// `Engine::reverse_group`.
+    #[serde(default)]
+    pub group: Option<u32>,
+    /// Free-form description, e.g. an external reference. Doesn't affect
+    /// processing.
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Partner assigned key identifying retries of the same transaction,
+    /// see `Engine::with_idempotency_keys`.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Line of the input file the transaction was read from, set by
+    /// `deserialize_transactions`.
+    #[serde(skip)]
+    pub line: Option<u64>,
+}
+
+impl Transaction {
+    /// Creates a transaction without any of the optional metadata.
+    pub fn new(ty: TransactionType, client: u16, tx: u32, amount: Option<Decimal>) -> Self {
+        Transaction {
+            ty,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            group: None,
+            memo: None,
+            idempotency_key: None,
+            line: None,
+        }
+    }
+}
+
+/// Row of a correction feed, see `Engine::apply_corrections`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Correction {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Decimal,
+}
+
+/// Same as `Transaction`, with the type given as an integer code.
+#[derive(Deserialize)]
+struct CodedTransaction {
+    #[serde(rename = "type", deserialize_with = "deserialize_type_code")]
+    ty: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    amount: Option<Decimal>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    group: Option<u32>,
+    #[serde(default)]
+    memo: Option<String>,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+impl From<CodedTransaction> for Transaction {
+    fn from(transaction: CodedTransaction) -> Self {
+        Transaction {
+            ty: transaction.ty,
+            client: transaction.client,
+            tx: transaction.tx,
+            amount: transaction.amount,
+            timestamp: transaction.timestamp,
+            group: transaction.group,
+            memo: transaction.memo,
+            idempotency_key: transaction.idempotency_key,
+            line: None,
+        }
+    }
+}
+
+fn deserialize_type_code<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<TransactionType, D::Error> {
+    let code = String::deserialize(deserializer)?;
+    TransactionType::from_code(&code)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown transaction type code: {}", code)))
+}
+
+/// Parses an amount given as a plain decimal with an optional sign, e.g.
+/// `-1000.50`. Up to 4 fractional digits are expected; more are accepted,
+/// but flagged by the linter. Anything else, including scientific notation
+/// like `1e3`, fails with `InputError::InvalidAmount` rather than being
+/// reinterpreted.
+pub fn parse_amount(amount: &str) -> Result<Decimal, InputError> {
+    let unsigned = amount
+        .strip_prefix(|c| c == '+' || c == '-')
+        .unwrap_or(amount);
+    let mut parts = unsigned.splitn(2, '.');
+    let integer = parts.next().unwrap_or_default();
+    let fraction = parts.next().unwrap_or_default();
+    let is_plain = !(integer.is_empty() && fraction.is_empty())
+        && integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit());
+    if !is_plain {
+        return Err(InputError::InvalidAmount);
+    }
+    amount.parse().map_err(|_| InputError::InvalidAmount)
+}
+
+fn deserialize_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Decimal>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(amount) => parse_amount(&amount)
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("invalid amount: {}", amount))),
+        None => Ok(None),
+    }
+}
+
+/// Deserializes transactions from `reader`, parsing the `type` column
+/// according to `encoding`. Each transaction carries the line it was read
+/// from in `Transaction::line`.
+pub fn deserialize_transactions<'a, R: Read + 'a>(
+    reader: csv::Reader<R>,
+    encoding: TypeEncoding,
+) -> Box<dyn Iterator<Item = csv::Result<Transaction>> + 'a> {
+    Box::new(
+        deserialize_transactions_with_lines(reader, encoding).map(|(_, transaction)| transaction),
+    )
+}
+
+/// Item of a feed read by `deserialize_batches`.
+#[derive(Clone, Debug)]
+pub enum BatchItem {
+    Transaction(Transaction),
+    /// End of a batch followed by another one.
+    Boundary,
+}
+
+/// Reads a CSV feed of batches separated by blank lines. The header comes
+/// only once, before the first batch. Blank lines at the start and end of
+/// the feed or repeated between batches don't produce extra boundaries.
+/// Each batch is buffered in memory before it's parsed.
+pub fn deserialize_batches<'a, R: BufRead + 'a>(
+    input: R,
+    encoding: TypeEncoding,
+) -> Box<dyn Iterator<Item = csv::Result<BatchItem>> + 'a> {
+    let mut lines = input.lines();
+    let mut line_number = 0;
+    let mut header: Option<String> = None;
+    let mut queue = VecDeque::new();
+    let mut started = false;
+    Box::new(std::iter::from_fn(move || {
+        if let Some(item) = queue.pop_front() {
+            return Some(item);
+        }
+        let mut batch = String::new();
+        let mut first_line = 0;
+        for line in &mut lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            line_number += 1;
+            if line.trim().is_empty() {
+                if batch.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            if header.is_none() {
+                header = Some(line);
+                continue;
+            }
+            if batch.is_empty() {
+                first_line = line_number;
+            }
+            batch.push_str(&line);
+            batch.push('\n');
+        }
+        if batch.is_empty() {
+            return None;
+        }
+        if started {
+            queue.push_back(Ok(BatchItem::Boundary));
+        }
+        started = true;
+        let header = header.as_deref().unwrap_or_default();
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(Cursor::new(format!("{}\n{}", header, batch)));
+        queue.extend(
+            deserialize_transactions(reader, encoding).map(|transaction| {
+                transaction.map(|transaction| {
+                    // Lines of the buffer start with the header, rows with 2.
+                    BatchItem::Transaction(Transaction {
+                        line: transaction.line.map(|line| line + first_line - 2),
+                        ..transaction
+                    })
+                })
+            }),
+        );
+        queue.pop_front()
+    }))
+}
+
+/// Same as `deserialize_transactions`, pairing every row with the line it
+/// starts on (the header is line 1), so rows which fail to parse can be
+/// reported too.
+pub fn deserialize_transactions_with_lines<'a, R: Read + 'a>(
+    reader: csv::Reader<R>,
+    encoding: TypeEncoding,
+) -> Box<dyn Iterator<Item = (u64, csv::Result<Transaction>)> + 'a> {
+    Box::new(read_rows(reader, encoding).map(|row| (row.line, row.transaction)))
+}
+
+/// Positional parsing of a row of a headerless file, see
+/// `TransactionReaderBuilder::has_headers`.
+impl TryFrom<&StringRecord> for Transaction {
+    type Error = InputError;
+
+    fn try_from(record: &StringRecord) -> Result<Self, Self::Error> {
+        parse_positional(record, TypeEncoding::Name)
+    }
+}
+
+/// Parses a row with columns in the standard `type,client,tx,amount` order.
+/// The amount may be omitted.
+fn parse_positional(
+    record: &StringRecord,
+    encoding: TypeEncoding,
+) -> Result<Transaction, InputError> {
+    let line = record.position().map(csv::Position::line);
+    let field = |index: usize| record.get(index).map_or("", str::trim);
+    let ty = encoding
+        .parse(field(0))
+        .ok_or_else(|| InputError::UnknownType {
+            line: line.unwrap_or_default(),
+            value: field(0).to_string(),
+        })?;
+    let client = field(1)
+        .parse()
+        .map_err(|_| InputError::InvalidField { column: "client" })?;
+    let tx = field(2)
+        .parse()
+        .map_err(|_| InputError::InvalidField { column: "tx" })?;
+    let amount = match field(3) {
+        "" => None,
+        amount => Some(parse_amount(amount)?),
+    };
+    Ok(Transaction {
+        line,
+        ..Transaction::new(ty, client, tx, amount)
+    })
+}
+
+/// Reader of transactions with options beyond `deserialize_transactions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransactionReaderBuilder {
+    pub(crate) type_encoding: TypeEncoding,
+    pub(crate) strict_types: bool,
+    pub(crate) has_headers: bool,
+}
+
+impl Default for TransactionReaderBuilder {
+    fn default() -> Self {
+        TransactionReaderBuilder {
+            type_encoding: TypeEncoding::default(),
+            strict_types: false,
+            has_headers: true,
+        }
+    }
+}
+
+impl TransactionReaderBuilder {
+    pub fn new() -> Self {
+        TransactionReaderBuilder::default()
+    }
+
+    pub fn type_encoding(mut self, type_encoding: TypeEncoding) -> Self {
+        self.type_encoding = type_encoding;
+        self
+    }
+
+    /// When enabled, reading stops at the first row with an unknown `type`,
+    /// yielding `InputError::UnknownType` as the last item, as it usually
+    /// means a format mismatch rather than a single bad row. Otherwise such
+    /// rows fail one by one with `InputError::UnknownType`.
+    pub fn strict_types(mut self, strict_types: bool) -> Self {
+        self.strict_types = strict_types;
+        self
+    }
+
+    /// Whether the input of `from_input` starts with a header row. Without
+    /// one, columns are taken in the standard `type,client,tx,amount` order,
+    /// as in `Transaction::try_from`. Enabled by default.
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Reads transactions from raw input, trimming whitespace around fields.
+    pub fn from_input<'a, R: Read + 'a>(
+        self,
+        input: R,
+    ) -> Box<dyn Iterator<Item = Result<Transaction, InputError>> + 'a> {
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .has_headers(self.has_headers)
+            .flexible(!self.has_headers)
+            .from_reader(input);
+        self.from_reader(reader)
+    }
+
+    /// Parses a single record read from line `line` the way `from_reader`
+    /// does, by `headers` if the input has them, positionally otherwise.
+    #[cfg(feature = "async")]
+    pub(crate) fn parse_record(
+        self,
+        record: &StringRecord,
+        headers: Option<&StringRecord>,
+        line: u64,
+    ) -> Result<Transaction, InputError> {
+        match headers {
+            Some(headers) => {
+                parse_row(record, Some(headers), self.type_encoding, line).into_result()
+            }
+            None => parse_positional(record, self.type_encoding),
+        }
+    }
+
+    /// Reads transactions from a CSV reader, parsing rows positionally if it
+    /// was configured without headers.
+    pub fn from_reader<'a, R: Read + 'a>(
+        self,
+        reader: csv::Reader<R>,
+    ) -> Box<dyn Iterator<Item = Result<Transaction, InputError>> + 'a> {
+        let encoding = self.type_encoding;
+        let transactions: Box<dyn Iterator<Item = Result<Transaction, InputError>> + 'a> =
+            if reader.has_headers() {
+                Box::new(read_rows(reader, encoding).map(Row::into_result))
+            } else {
+                Box::new(
+                    reader
+                        .into_records()
+                        .map(move |record| parse_positional(&record?, encoding)),
+                )
+            };
+        let strict_types = self.strict_types;
+        Box::new(transactions.scan(false, move |stopped, transaction| {
+            if *stopped {
+                return None;
+            }
+            *stopped = strict_types && matches!(transaction, Err(InputError::UnknownType { .. }));
+            Some(transaction)
+        }))
+    }
+}
+
+struct Row {
+    line: u64,
+    transaction: csv::Result<Transaction>,
+    /// Value of the `type` column of a row which failed to parse, if it
+    /// isn't a known type.
+    unknown_type: Option<String>,
+}
+
+impl Row {
+    fn into_result(self) -> Result<Transaction, InputError> {
+        match (self.transaction, self.unknown_type) {
+            (Err(_), Some(value)) => Err(InputError::UnknownType {
+                line: self.line,
+                value,
+            }),
+            (transaction, _) => transaction.map_err(InputError::from),
+        }
+    }
+}
+
+fn read_rows<'a, R: Read + 'a>(
+    mut reader: csv::Reader<R>,
+    encoding: TypeEncoding,
+) -> Box<dyn Iterator<Item = Row> + 'a> {
+    let headers = if reader.has_headers() {
+        match reader.headers() {
+            Ok(headers) => Some(headers.clone()),
+            Err(err) => {
+                return Box::new(std::iter::once(Row {
+                    line: 1,
+                    transaction: Err(err),
+                    unknown_type: None,
+                }))
+            }
+        }
+    } else {
+        None
+    };
+    let mut records = reader.into_records();
+    Box::new(std::iter::from_fn(move || {
+        let next_line = records.reader().position().line();
+        let record = match records.next()? {
+            Ok(record) => record,
+            Err(err) => {
+                return Some(Row {
+                    line: err.position().map_or(next_line, csv::Position::line),
+                    transaction: Err(err),
+                    unknown_type: None,
+                })
+            }
+        };
+        let line = record.position().map_or(next_line, csv::Position::line);
+        Some(parse_row(&record, headers.as_ref(), encoding, line))
+    }))
+}
+
+fn parse_row(
+    record: &StringRecord,
+    headers: Option<&StringRecord>,
+    encoding: TypeEncoding,
+    line: u64,
+) -> Row {
+    let transaction = match encoding {
+        TypeEncoding::Name => record.deserialize(headers),
+        TypeEncoding::Code => record
+            .deserialize::<CodedTransaction>(headers)
+            .map(Into::into),
+    };
+    let type_column = headers.map_or(Some(0), |headers| {
+        headers.iter().position(|header| header == "type")
+    });
+    let unknown_type = type_column
+        .and_then(|column| record.get(column))
+        .filter(|ty| transaction.is_err() && encoding.parse(ty).is_none())
+        .map(str::to_string);
+    Row {
+        line,
+        transaction: transaction.map(|transaction: Transaction| Transaction {
+            line: Some(line),
+            ..transaction
+        }),
+        unknown_type,
+    }
+}
+
+/// Renames non-standard CSV headers onto `Transaction` field names.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderMapping {
+    names: HashMap<String, String>,
+}
+
+impl HeaderMapping {
+    pub fn new() -> Self {
+        HeaderMapping::default()
+    }
+
+    /// Alternate names seen in partner feeds: `txn_type`, `account`,
+    /// `transaction_id` and `value`.
+    pub fn common_alternates() -> Self {
+        HeaderMapping::new()
+            .with("txn_type", "type")
+            .with("account", "client")
+            .with("transaction_id", "tx")
+            .with("value", "amount")
+    }
+
+    pub fn with(mut self, from: &str, to: &str) -> Self {
+        self.names.insert(from.to_string(), to.to_string());
+        self
+    }
+
+    /// Renames headers of `reader`. Must be called before reading any
+    /// records. Headers without a mapping are kept.
+    pub fn apply<R: Read>(&self, reader: &mut csv::Reader<R>) -> csv::Result<()> {
+        let headers = reader
+            .headers()?
+            .iter()
+            .map(|header| self.names.get(header).map_or(header, String::as_str))
+            .collect();
+        reader.set_headers(headers);
+        Ok(())
+    }
+}
+
+/// Same as `Transaction`, with the amount given either as a JSON number or a
+/// string.
+#[derive(Deserialize)]
+struct JsonTransaction {
+    #[serde(rename = "type")]
+    ty: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(default)]
+    amount: Option<serde_json::Value>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    group: Option<u32>,
+    #[serde(default)]
+    memo: Option<String>,
+    #[serde(default)]
+    idempotency_key: Option<String>,
+}
+
+/// Parses a single JSONL row. An amount which doesn't fit a `Decimal` (e.g. a
+/// huge float) fails with `InputError::InvalidAmount` rather than being
+/// treated as missing.
+pub fn parse_jsonl_transaction(line: &str) -> Result<Transaction, InputError> {
+    let transaction: JsonTransaction = serde_json::from_str(line)?;
+    let amount = match transaction.amount {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(amount)) => Some(parse_amount(&amount)?),
+        Some(serde_json::Value::Number(amount)) => Some(
+            amount
+                .to_string()
+                .parse()
+                .ok()
+                .or_else(|| amount.as_f64().and_then(Decimal::from_f64))
+                .ok_or(InputError::InvalidAmount)?,
+        ),
+        Some(_) => return Err(InputError::InvalidAmount),
+    };
+    Ok(Transaction {
+        ty: transaction.ty,
+        client: transaction.client,
+        tx: transaction.tx,
+        amount,
+        timestamp: transaction.timestamp,
+        group: transaction.group,
+        memo: transaction.memo,
+        idempotency_key: transaction.idempotency_key,
+        line: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::{value, IntoDeserializer};
+
+    use super::*;
+
+    mod deserialize_type_code {
+        use super::*;
+
+        fn parse(code: &str) -> Result<TransactionType, value::Error> {
+            deserialize_type_code(code.into_deserializer())
+        }
+
+        #[test]
+        fn should_parse_deposit() {
+            assert!(matches!(parse("1"), Ok(TransactionType::Deposit)));
+        }
+
+        #[test]
+        fn should_parse_chargeback() {
+            assert!(matches!(parse("5"), Ok(TransactionType::Chargeback)));
+        }
+
+        #[test]
+        fn should_fail_on_unknown_code() {
+            assert!(parse("9").is_err());
+        }
+    }
+
+    mod parse_jsonl_transaction {
+        use super::*;
+
+        #[test]
+        fn should_parse_float_amount() {
+            let transaction = parse_jsonl_transaction(
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}"#,
+            )
+            .unwrap();
+            assert!(matches!(transaction.ty, TransactionType::Deposit));
+            assert_eq!(transaction.amount, Some(Decimal::new(15, 1)));
+        }
+
+        #[test]
+        fn should_treat_null_amount_as_missing() {
+            let transaction = parse_jsonl_transaction(
+                r#"{"type": "dispute", "client": 1, "tx": 1, "amount": null}"#,
+            )
+            .unwrap();
+            assert_eq!(transaction.amount, None);
+        }
+
+        #[test]
+        fn should_reject_huge_float_amount() {
+            let result = parse_jsonl_transaction(
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1e300}"#,
+            );
+            assert!(matches!(result, Err(InputError::InvalidAmount)));
+        }
+    }
+
+    mod parse_amount {
+        use super::*;
+
+        #[test]
+        fn should_reject_scientific_notation() {
+            assert!(matches!(
+                parse_amount("1e3"),
+                Err(InputError::InvalidAmount)
+            ));
+            let reader =
+                csv::Reader::from_reader("type,client,tx,amount\ndeposit,1,1,1e3\n".as_bytes());
+            let transactions: Vec<csv::Result<Transaction>> =
+                deserialize_transactions(reader, TypeEncoding::Name).collect();
+            assert!(transactions[0].is_err());
+        }
+
+        #[test]
+        fn should_accept_plain_decimals() {
+            assert_eq!(parse_amount("1000").unwrap(), Decimal::new(1000, 0));
+            assert_eq!(parse_amount("1000.50").unwrap(), Decimal::new(100050, 2));
+            assert_eq!(parse_amount("-0.5").unwrap(), Decimal::new(-5, 1));
+        }
+    }
+
+    mod header_mapping {
+        use rust_decimal::Decimal;
+
+        use super::*;
+
+        #[test]
+        fn should_parse_alternate_headers() {
+            let input = "txn_type,account,transaction_id,value\ndeposit,1,2,1.5\n";
+            let mut reader = csv::Reader::from_reader(input.as_bytes());
+            HeaderMapping::common_alternates()
+                .apply(&mut reader)
+                .unwrap();
+            let transactions: Vec<Transaction> =
+                reader.into_deserialize().collect::<Result<_, _>>().unwrap();
+            assert_eq!(transactions.len(), 1);
+            assert!(matches!(transactions[0].ty, TransactionType::Deposit));
+            assert_eq!(transactions[0].client, 1);
+            assert_eq!(transactions[0].tx, 2);
+            assert_eq!(transactions[0].amount, Some(Decimal::new(15, 1)));
+        }
+    }
+
+    mod headerless {
+        use super::*;
+
+        #[test]
+        fn should_parse_rows_positionally() {
+            let input = "deposit, 1, 1, 1.5\n\
+                         dispute, 1, 1\n";
+            let transactions: Vec<Transaction> = TransactionReaderBuilder::new()
+                .has_headers(false)
+                .from_input(input.as_bytes())
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(transactions.len(), 2);
+            assert!(matches!(transactions[0].ty, TransactionType::Deposit));
+            assert_eq!(
+                (
+                    transactions[0].client,
+                    transactions[0].tx,
+                    transactions[0].amount
+                ),
+                (1, 1, Some(Decimal::new(15, 1)))
+            );
+            assert_eq!(transactions[0].line, Some(1));
+            assert!(matches!(transactions[1].ty, TransactionType::Dispute));
+            assert_eq!(transactions[1].amount, None);
+        }
+
+        #[test]
+        fn should_report_invalid_field() {
+            let record = StringRecord::from(vec!["deposit", "x", "1", "1.0"]);
+            assert!(matches!(
+                Transaction::try_from(&record),
+                Err(InputError::InvalidField { column: "client" })
+            ));
+        }
+    }
+
+    mod deserialize_batches {
+        use super::*;
+
+        #[test]
+        fn should_ignore_blank_lines_around_batches() {
+            let input = "type,client,tx,amount\n\
+                         \n\
+                         deposit,1,1,1.0\n\
+                         \n\
+                         \n\
+                         deposit,1,2,1.0\n\
+                         \n";
+            let items: Vec<BatchItem> = deserialize_batches(input.as_bytes(), TypeEncoding::Name)
+                .collect::<csv::Result<_>>()
+                .unwrap();
+            assert_eq!(items.len(), 3);
+            match (&items[0], &items[1], &items[2]) {
+                (
+                    BatchItem::Transaction(first),
+                    BatchItem::Boundary,
+                    BatchItem::Transaction(second),
+                ) => {
+                    assert_eq!((first.tx, first.line), (1, Some(3)));
+                    assert_eq!((second.tx, second.line), (2, Some(6)));
+                }
+                other => panic!("unexpected items: {:?}", other),
+            }
+        }
+    }
+
+    mod transaction_reader_builder {
+        use super::*;
+
+        const INPUT: &str = "type,client,tx,amount\n\
+                             deposit,1,1,1.0\n\
+                             transfer,1,2,1.0\n\
+                             deposit,1,3,2.0\n";
+
+        #[test]
+        fn should_stop_on_unknown_type_when_strict() {
+            let reader = csv::Reader::from_reader(INPUT.as_bytes());
+            let rows: Vec<Result<Transaction, InputError>> = TransactionReaderBuilder::new()
+                .strict_types(true)
+                .from_reader(reader)
+                .collect();
+            assert_eq!(rows.len(), 2);
+            assert_eq!(rows[0].as_ref().unwrap().tx, 1);
+            match &rows[1] {
+                Err(InputError::UnknownType { line, value }) => {
+                    assert_eq!(*line, 3);
+                    assert_eq!(value, "transfer");
+                }
+                other => panic!("unexpected row: {:?}", other),
+            }
+        }
+
+        #[test]
+        fn should_skip_unknown_type_when_lenient() {
+            let reader = csv::Reader::from_reader(INPUT.as_bytes());
+            let transactions: Vec<Transaction> = TransactionReaderBuilder::new()
+                .from_reader(reader)
+                .filter_map(Result::ok)
+                .collect();
+            let txs: Vec<u32> = transactions
+                .iter()
+                .map(|transaction| transaction.tx)
+                .collect();
+            assert_eq!(txs, vec![1, 3]);
+        }
+    }
+
+    mod deserialize_transactions {
+        use super::*;
+        use crate::{engine::Engine, errors::TransactionProcessingError, ids::ClientId};
+
+        #[test]
+        fn should_pair_rows_with_lines() {
+            let input = "type,client,tx,amount\n\
+                         deposit,1,1,1.0\n\
+                         bogus,1,2,1.0\n\
+                         withdrawal,1,3,0.5\n";
+            let reader = csv::Reader::from_reader(input.as_bytes());
+            let rows: Vec<(u64, csv::Result<Transaction>)> =
+                deserialize_transactions_with_lines(reader, TypeEncoding::Name).collect();
+            let lines: Vec<u64> = rows.iter().map(|(line, _)| *line).collect();
+            assert_eq!(lines, vec![2, 3, 4]);
+            assert!(rows[1].1.is_err());
+            assert_eq!(rows[2].1.as_ref().unwrap().tx, 3);
+            assert_eq!(rows[2].1.as_ref().unwrap().line, Some(4));
+        }
+
+        #[test]
+        fn should_yield_nothing_for_empty_input() {
+            for input in &["", "type,client,tx,amount\n"] {
+                let reader = csv::Reader::from_reader(input.as_bytes());
+                let transactions: Vec<csv::Result<Transaction>> =
+                    deserialize_transactions(reader, TypeEncoding::Name).collect();
+                assert!(transactions.is_empty());
+            }
+        }
+
+        fn read_trimmed(input: &str) -> Vec<csv::Result<Transaction>> {
+            let reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input.as_bytes());
+            deserialize_transactions(reader, TypeEncoding::Name).collect()
+        }
+
+        #[test]
+        fn should_parse_every_transaction_type() {
+            let transactions = read_trimmed(
+                "type, client, tx, amount\n\
+                 deposit, 1, 1, 1.0\n\
+                 withdrawal, 2, 2, 0.5\n\
+                 dispute, 3, 1,\n\
+                 resolve, 4, 1,\n\
+                 chargeback, 5, 1,\n",
+            );
+            let expected = vec![
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(10, 1))),
+                Transaction::new(TransactionType::Withdrawal, 2, 2, Some(Decimal::new(5, 1))),
+                Transaction::new(TransactionType::Dispute, 3, 1, None),
+                Transaction::new(TransactionType::Resolve, 4, 1, None),
+                Transaction::new(TransactionType::Chargeback, 5, 1, None),
+            ];
+            assert_eq!(transactions.len(), expected.len());
+            for (line, (transaction, expected)) in (2..).zip(transactions.into_iter().zip(expected))
+            {
+                let expected = Transaction {
+                    line: Some(line),
+                    ..expected
+                };
+                assert_eq!(transaction.unwrap(), expected);
+            }
+        }
+
+        #[test]
+        fn should_honor_header_names() {
+            let transactions = read_trimmed("amount, tx, type, client\n2.5, 7, deposit, 3\n");
+            let transaction = transactions.into_iter().next().unwrap().unwrap();
+            assert_eq!(transaction.ty, TransactionType::Deposit);
+            assert_eq!((transaction.client, transaction.tx), (3, 7));
+            assert_eq!(transaction.amount, Some(Decimal::new(25, 1)));
+        }
+
+        #[test]
+        fn should_treat_whitespace_only_amount_of_deposit_as_not_specified() {
+            let mut transactions = read_trimmed("type,client,tx,amount\ndeposit,1,1,   \n");
+            let transaction = transactions.remove(0).unwrap();
+            assert_eq!(transaction.amount, None);
+            assert_eq!(
+                Engine::new().process_transaction(transaction),
+                Err(TransactionProcessingError::AmountNotSpecified)
+            );
+        }
+
+        #[test]
+        fn should_treat_whitespace_only_amount_of_dispute_as_no_amount() {
+            let transactions = read_trimmed(
+                "type,client,tx,amount\n\
+                 deposit,1,1,2.0\n\
+                 dispute,1,1,   \n",
+            );
+            let mut engine = Engine::new();
+            for transaction in transactions {
+                let transaction = transaction.unwrap();
+                engine.process_transaction(transaction).unwrap();
+            }
+            assert_eq!(engine.client(ClientId(1)).unwrap().held, Decimal::new(2, 0));
+        }
+
+        #[test]
+        fn should_reject_row_with_missing_amount_column() {
+            let transactions = read_trimmed("type,client,tx,amount\ndeposit,1,1\n");
+            assert_eq!(transactions.len(), 1);
+            assert!(transactions[0].is_err());
+        }
+    }
 }
@@ -1,3 +1,16 @@
+#[cfg(feature = "async")]
+mod async_file;
+#[cfg(feature = "async")]
+mod async_output;
 pub mod client;
+pub mod engine;
 pub mod errors;
+pub mod ids;
 pub mod input_types;
+pub mod lint;
+pub mod output;
+pub mod progress;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "sqlite")]
+mod sqlite;
@@ -0,0 +1,219 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use csv::{ReaderBuilder, StringRecord};
+
+use crate::input_types::{Transaction, TransactionType};
+
+/// Maximal number of decimal places of an amount.
+const MAX_AMOUNT_SCALE: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The row would be processed, but likely not as the partner intended.
+    Warning,
+    /// The row would be skipped or rejected.
+    Error,
+}
+
+/// Problem found on a single line of an input file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineDiagnostic {
+    pub line: u64,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Checks every row of a transactions CSV file without processing it,
+/// reporting problems by line number. Only failure to read the file is
+/// returned as an error.
+pub fn lint_file<P: AsRef<Path>>(path: P) -> Result<Vec<LineDiagnostic>, csv::Error> {
+    let mut reader = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let mut diagnostics = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        diagnostics.extend(lint_record(&record, &headers).into_iter().map(
+            |(severity, message)| LineDiagnostic {
+                line,
+                severity,
+                message,
+            },
+        ));
+    }
+    Ok(diagnostics)
+}
+
+/// Dispute referencing a deposit or withdrawal which doesn't precede it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrderingViolation {
+    /// Position of the dispute among the transactions, starting from 0.
+    pub index: usize,
+    pub client: u16,
+    pub tx: u32,
+    /// Whether the referenced transaction appears later on. When `false`, it
+    /// doesn't appear at all.
+    pub appears_later: bool,
+}
+
+/// Checks in a single pass that every dispute references a deposit or
+/// withdrawal of the same client which appears earlier. Violations are
+/// returned in input order.
+pub fn precheck_dispute_ordering(transactions: &[Transaction]) -> Vec<OrderingViolation> {
+    let mut seen = HashSet::new();
+    let mut premature: HashMap<(u16, u32), Vec<usize>> = HashMap::new();
+    for (index, transaction) in transactions.iter().enumerate() {
+        let key = (transaction.client, transaction.tx);
+        match transaction.ty {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize => {
+                seen.insert(key);
+            }
+            TransactionType::Dispute if !seen.contains(&key) => {
+                premature.entry(key).or_default().push(index);
+            }
+            _ => {}
+        }
+    }
+    let mut violations: Vec<OrderingViolation> = premature
+        .into_iter()
+        .flat_map(|((client, tx), indices)| {
+            let appears_later = seen.contains(&(client, tx));
+            indices.into_iter().map(move |index| OrderingViolation {
+                index,
+                client,
+                tx,
+                appears_later,
+            })
+        })
+        .collect();
+    violations.sort_unstable_by_key(|violation| violation.index);
+    violations
+}
+
+fn lint_record(record: &StringRecord, headers: &StringRecord) -> Vec<(Severity, String)> {
+    let out_of_range = [("client", u64::from(u16::MAX)), ("tx", u64::from(u32::MAX))]
+        .iter()
+        .filter_map(|(column, max)| {
+            let value = field(record, headers, column)?;
+            match value.parse::<u64>() {
+                Ok(id) if id > *max => Some((
+                    Severity::Error,
+                    format!("{} id out of range: {}", column, value),
+                )),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    if !out_of_range.is_empty() {
+        return out_of_range;
+    }
+    let transaction: Transaction = match record.deserialize(Some(headers)) {
+        Ok(transaction) => transaction,
+        Err(err) => return vec![(Severity::Error, format!("parse error: {}", err))],
+    };
+    let moves_funds = matches!(
+        transaction.ty,
+        TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize
+    );
+    match (moves_funds, transaction.amount) {
+        (true, None) => vec![(Severity::Error, "missing amount".to_string())],
+        (true, Some(amount)) if amount.is_sign_negative() || amount.is_zero() => {
+            vec![(Severity::Error, format!("non-positive amount: {}", amount))]
+        }
+        (true, Some(amount)) if amount.scale() > MAX_AMOUNT_SCALE => vec![(
+            Severity::Warning,
+            format!(
+                "amount has more than {} decimal places: {}",
+                MAX_AMOUNT_SCALE, amount
+            ),
+        )],
+        (false, Some(amount)) => vec![(
+            Severity::Warning,
+            format!("amount ignored for {:?}: {}", transaction.ty, amount),
+        )],
+        _ => vec![],
+    }
+}
+
+fn field<'a>(record: &'a StringRecord, headers: &StringRecord, column: &str) -> Option<&'a str> {
+    let index = headers.iter().position(|header| header == column)?;
+    record.get(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn should_report_each_problem_line() {
+        let path = std::env::temp_dir().join(format!(
+            "toy-payments-engine-lint-{}.csv",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 70000, 2, 1.0\n\
+             deposit, 1, 3, 1.00001\n\
+             transfer, 1, 4, 1.0\n\
+             withdrawal, 1, 5,\n\
+             dispute, 1, 1,\n",
+        )
+        .unwrap();
+
+        let diagnostics = lint_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.severity))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, Severity::Error),
+                (4, Severity::Warning),
+                (5, Severity::Error),
+                (6, Severity::Error),
+            ]
+        );
+        assert_eq!(diagnostics[0].message, "client id out of range: 70000");
+        assert_eq!(diagnostics[3].message, "missing amount");
+    }
+
+    #[test]
+    fn should_report_dispute_before_deposit() {
+        let transactions = vec![
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(1, 0))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(1, 0))),
+            Transaction::new(TransactionType::Dispute, 2, 3, None),
+        ];
+        assert_eq!(
+            precheck_dispute_ordering(&transactions),
+            vec![
+                OrderingViolation {
+                    index: 2,
+                    client: 1,
+                    tx: 2,
+                    appears_later: true,
+                },
+                OrderingViolation {
+                    index: 4,
+                    client: 2,
+                    tx: 3,
+                    appears_later: false,
+                },
+            ]
+        );
+    }
+}
@@ -1,45 +1,117 @@
-use csv::ReaderBuilder;
-use std::io::Write;
-use std::{collections::HashMap, env};
-use toy_payments_engine::client::Client;
-use toy_payments_engine::input_types::Transaction;
+use std::{
+    env,
+    fs::File,
+    io::{self, Read},
+};
+use toy_payments_engine::engine::Engine;
+use toy_payments_engine::errors::InputError;
+use toy_payments_engine::input_types::{TransactionReaderBuilder, TypeEncoding};
+use toy_payments_engine::lint::lint_file;
+use toy_payments_engine::output::{
+    write_clients_pretty, write_clients_streaming_with_config, OutputColumn, OutputConfig,
+};
+use toy_payments_engine::progress::{Progress, ProgressReader};
 
 fn main() {
-    let path: String = env::args().nth(1).unwrap();
+    let mut path = None;
+    let mut show_progress = false;
+    let mut lint = false;
+    let mut pretty = false;
+    let mut strict_types = false;
+    let mut has_headers = true;
+    let mut type_encoding = TypeEncoding::default();
+    let mut output_config = OutputConfig::default();
+    for arg in env::args().skip(1) {
+        if let Some(columns) = arg.strip_prefix("--columns=") {
+            output_config.columns = columns
+                .split(',')
+                .map(|name| {
+                    OutputColumn::from_name(name).unwrap_or_else(|| {
+                        eprintln!("unknown output column {:?}", name);
+                        std::process::exit(1);
+                    })
+                })
+                .collect();
+            continue;
+        }
+        match arg.as_str() {
+            "--lint" => lint = true,
+            "--no-headers" => has_headers = false,
+            "--pretty" => pretty = true,
+            "--progress" => show_progress = true,
+            "--strict-types" => strict_types = true,
+            "--type-codes" => type_encoding = TypeEncoding::Code,
+            _ => path = Some(arg),
+        }
+    }
+    // No path or `-` means stdin.
+    let path = path.filter(|path| path != "-");
 
-    let csv_reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(path)
-        .unwrap();
+    if lint {
+        let path = path.expect("--lint requires an input file");
+        for diagnostic in lint_file(path).unwrap() {
+            println!(
+                "line {}: {:?}: {}",
+                diagnostic.line, diagnostic.severity, diagnostic.message
+            );
+        }
+        return;
+    }
 
-    let mut clients: HashMap<u16, Client> = HashMap::new();
+    let (input, input_len): (Box<dyn Read>, Option<u64>) = match path {
+        Some(path) => {
+            let file = File::open(path).unwrap();
+            let file_len = file.metadata().unwrap().len();
+            (Box::new(file), Some(file_len))
+        }
+        None => (Box::new(io::stdin()), None),
+    };
+    let reader = ProgressReader::new(input);
+    let consumed = reader.consumed();
 
-    for transaction in csv_reader
-        .into_deserialize()
-        .filter_map(|x: Result<Transaction, _>| x.ok())
-    {
-        let client = clients
-            .entry(transaction.client)
-            .or_insert_with(Default::default);
+    let mut progress = if show_progress {
+        Some(Progress::new(input_len, io::stderr()))
+    } else {
+        None
+    };
 
-        client.process_transaction(transaction);
-    }
+    let mut engine = Engine::new();
 
-    let stdout = std::io::stdout();
-    let lock = stdout.lock();
-    let mut writer = std::io::BufWriter::new(lock);
-
-    writeln!(&mut writer, "client,available,held,total,locked").unwrap();
-    for (id, client) in clients {
-        writeln!(
-            &mut writer,
-            "{},{},{},{},{}",
-            id,
-            client.available,
-            client.held,
-            client.total(),
-            client.is_frozen
+    engine
+        .process(
+            TransactionReaderBuilder::new()
+                .type_encoding(type_encoding)
+                .strict_types(strict_types)
+                .has_headers(has_headers)
+                .from_input(reader)
+                .filter_map(|x| match x {
+                    Ok(transaction) => Some(transaction),
+                    Err(InputError::UnknownType { line, value }) => {
+                        eprintln!("line {}: unknown transaction type {:?}", line, value);
+                        std::process::exit(1);
+                    }
+                    Err(_) => None,
+                })
+                .inspect(|_| {
+                    if let Some(progress) = progress.as_mut() {
+                        progress.update(consumed.get()).unwrap();
+                    }
+                }),
         )
         .unwrap();
+
+    if let Some(progress) = progress.as_mut() {
+        progress.finish().unwrap();
+    }
+
+    let stdout = io::stdout();
+    let lock = stdout.lock();
+    let mut writer = io::BufWriter::new(lock);
+
+    if pretty {
+        write_clients_pretty(&engine.client_list(), &output_config, &mut writer).unwrap();
+    } else {
+        write_clients_streaming_with_config(engine.iter_clients(), &output_config, &mut writer)
+            .unwrap();
     }
 }
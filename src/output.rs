@@ -0,0 +1,794 @@
+use std::{collections::BTreeMap, io::Write};
+
+use crate::{
+    client::{Client, ClientSnapshot},
+    engine::ClientList,
+    errors::OutputError,
+    ids::ClientId,
+};
+
+/// Number of records `write_clients_streaming` writes between flushes.
+pub(crate) const STREAMING_FLUSH_EVERY: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputConfig {
+    /// Replaces real client ids with sequential ids starting from 1, assigned
+    /// in ascending order of the real ids.
+    pub anonymize_clients: bool,
+    /// Minimal width of the numeric columns of `write_clients_pretty`. Wider
+    /// values widen their column instead of being truncated.
+    pub pad_to_width: Option<usize>,
+    /// Columns of `write_clients_with_config` and
+    /// `write_clients_streaming_with_config`, in order. Defaults to the
+    /// standard ones.
+    pub columns: Vec<OutputColumn>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            anonymize_clients: false,
+            pad_to_width: None,
+            columns: OutputColumn::STANDARD.to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// Authorized deposits awaiting capture.
+    Pending,
+    /// Deposits and withdrawals under active dispute.
+    DisputedCount,
+}
+
+impl OutputColumn {
+    /// Columns of the standard `client,available,held,total,locked` format.
+    pub const STANDARD: [OutputColumn; 5] = [
+        OutputColumn::Client,
+        OutputColumn::Available,
+        OutputColumn::Held,
+        OutputColumn::Total,
+        OutputColumn::Locked,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputColumn::Client => "client",
+            OutputColumn::Available => "available",
+            OutputColumn::Held => "held",
+            OutputColumn::Total => "total",
+            OutputColumn::Locked => "locked",
+            OutputColumn::Pending => "pending",
+            OutputColumn::DisputedCount => "disputed_count",
+        }
+    }
+
+    /// Inverse of `name`.
+    pub fn from_name(name: &str) -> Option<OutputColumn> {
+        match name {
+            "client" => Some(OutputColumn::Client),
+            "available" => Some(OutputColumn::Available),
+            "held" => Some(OutputColumn::Held),
+            "total" => Some(OutputColumn::Total),
+            "locked" => Some(OutputColumn::Locked),
+            "pending" => Some(OutputColumn::Pending),
+            "disputed_count" => Some(OutputColumn::DisputedCount),
+            _ => None,
+        }
+    }
+
+    fn value(self, id: u32, client: &ClientSnapshot) -> String {
+        match self {
+            OutputColumn::Client => id.to_string(),
+            OutputColumn::Available => client.available.to_string(),
+            OutputColumn::Held => client.held.to_string(),
+            OutputColumn::Total => client.total().to_string(),
+            OutputColumn::Locked => client.is_frozen.to_string(),
+            OutputColumn::Pending => client.pending.to_string(),
+            OutputColumn::DisputedCount => client.disputed_count.to_string(),
+        }
+    }
+}
+
+/// Mapping between real and anonymized client ids.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientIdMapping {
+    anonymized: BTreeMap<u16, u32>,
+}
+
+impl ClientIdMapping {
+    fn new<'a>(ids: impl Iterator<Item = &'a u16>) -> Self {
+        ClientIdMapping {
+            anonymized: ids
+                .zip(1..)
+                .map(|(id, anonymized)| (*id, anonymized))
+                .collect(),
+        }
+    }
+
+    pub fn anonymized(&self, real: u16) -> Option<u32> {
+        self.anonymized.get(&real).copied()
+    }
+
+    pub fn real(&self, anonymized: u32) -> Option<u16> {
+        self.anonymized
+            .iter()
+            .find(|(_, id)| **id == anonymized)
+            .map(|(real, _)| *real)
+    }
+
+    pub fn len(&self) -> usize {
+        self.anonymized.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anonymized.is_empty()
+    }
+}
+
+/// Writes clients in the standard `client,available,held,total,locked` CSV
+/// format.
+pub fn write_clients<W: Write>(clients: &ClientList, w: &mut W) -> Result<(), OutputError> {
+    write_clients_with_config(clients, &OutputConfig::default(), w).map(|_| ())
+}
+
+/// Same as `write_clients`, adjusted by `config`. Returns the id mapping when
+/// client ids are anonymized.
+pub fn write_clients_with_config<W: Write>(
+    clients: &ClientList,
+    config: &OutputConfig,
+    w: &mut W,
+) -> Result<Option<ClientIdMapping>, OutputError> {
+    let mapping = client_id_mapping(clients, config);
+    write_config_header(config, w)?;
+    for (id, client) in clients {
+        write_config_row(config, output_id(&mapping, *id), client, w)?;
+    }
+    Ok(mapping)
+}
+
+fn write_config_header<W: Write>(config: &OutputConfig, w: &mut W) -> Result<(), OutputError> {
+    let header: Vec<&str> = config.columns.iter().map(|column| column.name()).collect();
+    writeln!(w, "{}", header.join(","))?;
+    Ok(())
+}
+
+fn write_config_row<W: Write>(
+    config: &OutputConfig,
+    id: u32,
+    client: &ClientSnapshot,
+    w: &mut W,
+) -> Result<(), OutputError> {
+    let row: Vec<String> = config
+        .columns
+        .iter()
+        .map(|column| column.value(id, client))
+        .collect();
+    writeln!(w, "{}", row.join(","))?;
+    Ok(())
+}
+
+/// Writes clients as a table for reading in a terminal rather than further
+/// processing. Columns are separated by two spaces, numbers are
+/// right-aligned to a common width per column, see
+/// `OutputConfig::pad_to_width`. Returns the id mapping when client ids are
+/// anonymized.
+pub fn write_clients_pretty<W: Write>(
+    clients: &ClientList,
+    config: &OutputConfig,
+    w: &mut W,
+) -> Result<Option<ClientIdMapping>, OutputError> {
+    let mapping = client_id_mapping(clients, config);
+    let mut rows = vec![[
+        "client".to_string(),
+        "available".to_string(),
+        "held".to_string(),
+        "total".to_string(),
+        "locked".to_string(),
+    ]];
+    rows.extend(clients.iter().map(|(id, client)| {
+        [
+            output_id(&mapping, *id).to_string(),
+            client.available.to_string(),
+            client.held.to_string(),
+            client.total().to_string(),
+            client.is_frozen.to_string(),
+        ]
+    }));
+    let mut widths = [config.pad_to_width.unwrap_or(0); 4];
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+    for [id, available, held, total, locked] in &rows {
+        writeln!(
+            w,
+            "{:>client_width$}  {:>available_width$}  {:>held_width$}  {:>total_width$}  {}",
+            id,
+            available,
+            held,
+            total,
+            locked,
+            client_width = widths[0],
+            available_width = widths[1],
+            held_width = widths[2],
+            total_width = widths[3],
+        )?;
+    }
+    Ok(mapping)
+}
+
+/// Writes clients as a GitHub-flavored Markdown table with the standard
+/// columns, in ascending client id order, for reports and PR descriptions.
+pub fn write_clients_markdown<W: Write>(
+    clients: &ClientList,
+    w: &mut W,
+) -> Result<(), OutputError> {
+    writeln!(w, "| client | available | held | total | locked |")?;
+    writeln!(w, "| ---: | ---: | ---: | ---: | --- |")?;
+    for (id, client) in clients {
+        writeln!(
+            w,
+            "| {} | {} | {} | {} | {} |",
+            id,
+            client.available,
+            client.held,
+            client.total(),
+            client.is_frozen
+        )?;
+    }
+    Ok(())
+}
+
+fn client_id_mapping(clients: &ClientList, config: &OutputConfig) -> Option<ClientIdMapping> {
+    if config.anonymize_clients {
+        Some(ClientIdMapping::new(clients.keys()))
+    } else {
+        None
+    }
+}
+
+fn output_id(mapping: &Option<ClientIdMapping>, id: u16) -> u32 {
+    match mapping {
+        Some(mapping) => mapping.anonymized(id).unwrap_or_default(),
+        None => u32::from(id),
+    }
+}
+
+/// Same as `write_clients`, but takes clients straight from the engine (see
+/// `Engine::iter_clients`) instead of a `ClientList`, so no copy of the whole
+/// state is built. The writer is flushed periodically.
+pub fn write_clients_streaming<'a, I, W>(clients: I, w: &mut W) -> Result<(), OutputError>
+where
+    I: IntoIterator<Item = (ClientId, &'a Client)>,
+    W: Write,
+{
+    write_clients_streaming_with_config(clients, &OutputConfig::default(), w).map(|_| ())
+}
+
+/// Same as `write_clients_with_config`, streaming like
+/// `write_clients_streaming`. Clients must come in ascending id order, as
+/// `Engine::iter_clients` yields them, for anonymized ids to match.
+pub fn write_clients_streaming_with_config<'a, I, W>(
+    clients: I,
+    config: &OutputConfig,
+    w: &mut W,
+) -> Result<Option<ClientIdMapping>, OutputError>
+where
+    I: IntoIterator<Item = (ClientId, &'a Client)>,
+    W: Write,
+{
+    let mut anonymized = BTreeMap::new();
+    write_config_header(config, w)?;
+    for (written, (id, client)) in clients.into_iter().enumerate() {
+        let output_id = if config.anonymize_clients {
+            let output_id = written as u32 + 1;
+            anonymized.insert(id.0, output_id);
+            output_id
+        } else {
+            u32::from(id.0)
+        };
+        write_config_row(config, output_id, &client.snapshot(), w)?;
+        if (written + 1) % STREAMING_FLUSH_EVERY == 0 {
+            w.flush()?;
+        }
+    }
+    w.flush()?;
+    Ok(if config.anonymize_clients {
+        Some(ClientIdMapping { anonymized })
+    } else {
+        None
+    })
+}
+
+pub(crate) fn write_header<W: Write>(w: &mut W) -> Result<(), OutputError> {
+    writeln!(w, "client,available,held,total,locked")?;
+    Ok(())
+}
+
+pub(crate) fn write_row<W: Write>(
+    w: &mut W,
+    id: u32,
+    client: &ClientSnapshot,
+) -> Result<(), OutputError> {
+    writeln!(
+        w,
+        "{},{},{},{},{}",
+        id,
+        client.available,
+        client.held,
+        client.total(),
+        client.is_frozen
+    )?;
+    Ok(())
+}
+
+/// Destination of the final state, see `Engine::write_all`.
+pub trait OutputSink {
+    /// Called once before the first client.
+    fn begin(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn write_client(&mut self, id: u16, client: &ClientSnapshot) -> Result<(), OutputError>;
+
+    /// Called once after the last client.
+    fn finish(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/// Sink writing clients in the `write_clients` CSV format.
+pub struct CsvSink<W> {
+    w: W,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(w: W) -> Self {
+        CsvSink { w }
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn begin(&mut self) -> Result<(), OutputError> {
+        write_header(&mut self.w)
+    }
+
+    fn write_client(&mut self, id: u16, client: &ClientSnapshot) -> Result<(), OutputError> {
+        write_row(&mut self.w, u32::from(id), client)
+    }
+
+    fn finish(&mut self) -> Result<(), OutputError> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+/// Sink writing clients as JSON lines, one object per client. Amounts are
+/// strings to keep their precision, as in the server's responses.
+pub struct JsonLinesSink<W> {
+    w: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(w: W) -> Self {
+        JsonLinesSink { w }
+    }
+}
+
+impl<W: Write> OutputSink for JsonLinesSink<W> {
+    fn write_client(&mut self, id: u16, client: &ClientSnapshot) -> Result<(), OutputError> {
+        let line = serde_json::json!({
+            "client": id,
+            "available": client.available.to_string(),
+            "held": client.held.to_string(),
+            "total": client.total().to_string(),
+            "locked": client.is_frozen,
+        });
+        writeln!(self.w, "{}", line)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), OutputError> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+/// Column widths for `write_clients_fixed_width`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixedWidths {
+    pub client: usize,
+    pub available: usize,
+    pub held: usize,
+    pub total: usize,
+    pub locked: usize,
+}
+
+impl Default for FixedWidths {
+    fn default() -> Self {
+        FixedWidths {
+            client: 5,
+            available: 32,
+            held: 32,
+            total: 32,
+            locked: 5,
+        }
+    }
+}
+
+/// Writes clients as fixed-width records without a header, one per line.
+/// Numbers are right-aligned and `locked` is left-aligned. A value which
+/// doesn't fit its column fails with `OutputError::ValueTooWide` instead of
+/// being truncated.
+pub fn write_clients_fixed_width<W: Write>(
+    clients: &ClientList,
+    widths: &FixedWidths,
+    w: &mut W,
+) -> Result<(), OutputError> {
+    for (id, client) in clients {
+        let id = fit_to_width("client", id.to_string(), widths.client)?;
+        let available = fit_to_width("available", client.available.to_string(), widths.available)?;
+        let held = fit_to_width("held", client.held.to_string(), widths.held)?;
+        let total = fit_to_width("total", client.total().to_string(), widths.total)?;
+        let locked = fit_to_width("locked", client.is_frozen.to_string(), widths.locked)?;
+        writeln!(
+            w,
+            "{:>client_width$}{:>available_width$}{:>held_width$}{:>total_width$}{:<locked_width$}",
+            id,
+            available,
+            held,
+            total,
+            locked,
+            client_width = widths.client,
+            available_width = widths.available,
+            held_width = widths.held,
+            total_width = widths.total,
+            locked_width = widths.locked,
+        )?;
+    }
+    Ok(())
+}
+
+fn fit_to_width(column: &'static str, value: String, width: usize) -> Result<String, OutputError> {
+    if value.len() > width {
+        return Err(OutputError::ValueTooWide {
+            column,
+            value,
+            width,
+        });
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn test_clients() -> ClientList {
+        let mut clients = ClientList::new();
+        clients.insert(
+            1,
+            ClientSnapshot {
+                available: Decimal::new(15, 1),
+                held: Decimal::new(0, 0),
+                pending: Decimal::new(0, 0),
+                is_frozen: false,
+                disputed_count: 0,
+            },
+        );
+        clients.insert(
+            2,
+            ClientSnapshot {
+                available: Decimal::new(2, 0),
+                held: Decimal::new(10, 1),
+                pending: Decimal::new(0, 0),
+                is_frozen: true,
+                disputed_count: 1,
+            },
+        );
+        clients
+    }
+
+    mod write_clients {
+        use super::*;
+
+        #[test]
+        fn should_write_csv() {
+            let mut output = Vec::new();
+            write_clients(&test_clients(), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client,available,held,total,locked\n\
+                 1,1.5,0,1.5,false\n\
+                 2,2,1.0,3.0,true\n"
+            );
+        }
+    }
+
+    mod write_clients_with_config {
+        use super::*;
+
+        #[test]
+        fn should_anonymize_client_ids() {
+            let mut clients = test_clients();
+            let second = clients.remove(&2).unwrap();
+            clients.insert(42, second);
+            let first = clients.remove(&1).unwrap();
+            clients.insert(7, first);
+            let config = OutputConfig {
+                anonymize_clients: true,
+                ..OutputConfig::default()
+            };
+            let mut output = Vec::new();
+
+            let mapping = write_clients_with_config(&clients, &config, &mut output)
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client,available,held,total,locked\n\
+                 1,1.5,0,1.5,false\n\
+                 2,2,1.0,3.0,true\n"
+            );
+            assert_eq!(mapping.len(), 2);
+            assert_eq!(mapping.anonymized(7), Some(1));
+            assert_eq!(mapping.anonymized(42), Some(2));
+            for real in &[7, 42] {
+                assert_eq!(
+                    mapping.real(mapping.anonymized(*real).unwrap()),
+                    Some(*real)
+                );
+            }
+            assert_eq!(mapping.anonymized(1), None);
+        }
+
+        #[test]
+        fn should_keep_ids_by_default() {
+            let mut output = Vec::new();
+            let mapping =
+                write_clients_with_config(&test_clients(), &OutputConfig::default(), &mut output)
+                    .unwrap();
+            assert!(mapping.is_none());
+        }
+
+        #[test]
+        fn should_write_selected_columns() {
+            let config = OutputConfig {
+                columns: vec![OutputColumn::Client, OutputColumn::Total],
+                ..OutputConfig::default()
+            };
+            let mut output = Vec::new();
+            write_clients_with_config(&test_clients(), &config, &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client,total\n\
+                 1,1.5\n\
+                 2,3.0\n"
+            );
+        }
+
+        #[test]
+        fn should_write_disputed_count() {
+            let config = OutputConfig {
+                columns: vec![OutputColumn::Client, OutputColumn::DisputedCount],
+                ..OutputConfig::default()
+            };
+            let mut output = Vec::new();
+            write_clients_with_config(&test_clients(), &config, &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client,disputed_count\n\
+                 1,0\n\
+                 2,1\n"
+            );
+        }
+
+        #[test]
+        fn should_write_pending_funds() {
+            let mut clients = test_clients();
+            clients.get_mut(&1).unwrap().pending = Decimal::new(25, 1);
+            let config = OutputConfig {
+                columns: vec![
+                    OutputColumn::Client,
+                    OutputColumn::Total,
+                    OutputColumn::Pending,
+                ],
+                ..OutputConfig::default()
+            };
+            let mut output = Vec::new();
+            write_clients_with_config(&clients, &config, &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client,total,pending\n\
+                 1,1.5,2.5\n\
+                 2,3.0,0\n"
+            );
+        }
+
+        #[test]
+        fn should_parse_column_names() {
+            for column in OutputColumn::STANDARD
+                .iter()
+                .chain(&[OutputColumn::Pending, OutputColumn::DisputedCount])
+            {
+                assert_eq!(OutputColumn::from_name(column.name()), Some(*column));
+            }
+            assert_eq!(OutputColumn::from_name("frozen"), None);
+        }
+    }
+
+    mod write_clients_pretty {
+        use super::*;
+
+        #[test]
+        fn should_align_columns() {
+            let config = OutputConfig {
+                pad_to_width: Some(5),
+                ..OutputConfig::default()
+            };
+            let mut output = Vec::new();
+            write_clients_pretty(&test_clients(), &config, &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "client  available   held  total  locked\n     \
+                      1        1.5      0    1.5  false\n     \
+                      2          2    1.0    3.0  true\n"
+            );
+        }
+    }
+
+    mod write_clients_markdown {
+        use super::*;
+
+        #[test]
+        fn should_write_table() {
+            let mut output = Vec::new();
+            write_clients_markdown(&test_clients(), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "| client | available | held | total | locked |\n\
+                 | ---: | ---: | ---: | ---: | --- |\n\
+                 | 1 | 1.5 | 0 | 1.5 | false |\n\
+                 | 2 | 2 | 1.0 | 3.0 | true |\n"
+            );
+        }
+    }
+
+    mod write_clients_streaming {
+        use super::*;
+        use crate::{
+            engine::Engine,
+            input_types::{Transaction, TransactionType},
+        };
+
+        #[test]
+        fn should_match_buffered_output() {
+            let mut engine = Engine::new();
+            for client in 1..=3 {
+                engine
+                    .process_transaction(Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        u32::from(client),
+                        Some(Decimal::new(i64::from(client) * 15, 1)),
+                    ))
+                    .unwrap();
+            }
+            engine
+                .process_transaction(Transaction::new(TransactionType::Dispute, 2, 2, None))
+                .unwrap();
+
+            let mut buffered = Vec::new();
+            write_clients(&engine.client_list(), &mut buffered).unwrap();
+            let mut streamed = Vec::new();
+            write_clients_streaming(engine.iter_clients(), &mut streamed).unwrap();
+
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                String::from_utf8(buffered).unwrap()
+            );
+        }
+
+        #[test]
+        fn should_match_buffered_output_with_config() {
+            let mut engine = Engine::new();
+            for client in &[7, 42] {
+                engine
+                    .process_transaction(Transaction::new(
+                        TransactionType::Deposit,
+                        *client,
+                        u32::from(*client),
+                        Some(Decimal::new(5, 0)),
+                    ))
+                    .unwrap();
+            }
+            engine
+                .process_transaction(Transaction::new(TransactionType::Dispute, 42, 42, None))
+                .unwrap();
+            let config = OutputConfig {
+                anonymize_clients: true,
+                columns: vec![
+                    OutputColumn::Client,
+                    OutputColumn::Held,
+                    OutputColumn::DisputedCount,
+                ],
+                ..OutputConfig::default()
+            };
+
+            let mut buffered = Vec::new();
+            let buffered_mapping =
+                write_clients_with_config(&engine.client_list(), &config, &mut buffered).unwrap();
+            let mut streamed = Vec::new();
+            let streamed_mapping =
+                write_clients_streaming_with_config(engine.iter_clients(), &config, &mut streamed)
+                    .unwrap();
+
+            let streamed = String::from_utf8(streamed).unwrap();
+            assert_eq!(
+                streamed,
+                "client,held,disputed_count\n\
+                 1,0,0\n\
+                 2,5,1\n"
+            );
+            assert_eq!(streamed, String::from_utf8(buffered).unwrap());
+            assert_eq!(streamed_mapping, buffered_mapping);
+        }
+    }
+
+    mod write_clients_fixed_width {
+        use super::*;
+
+        fn widths() -> FixedWidths {
+            FixedWidths {
+                client: 3,
+                available: 6,
+                held: 6,
+                total: 6,
+                locked: 6,
+            }
+        }
+
+        #[test]
+        fn should_pad_columns() {
+            let mut output = Vec::new();
+            write_clients_fixed_width(&test_clients(), &widths(), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "  1   1.5     0   1.5false \n  2     2   1.0   3.0true  \n"
+            );
+        }
+
+        #[test]
+        fn should_fail_on_too_wide_value() {
+            let mut output = Vec::new();
+            let widths = FixedWidths {
+                available: 2,
+                ..widths()
+            };
+            let result = write_clients_fixed_width(&test_clients(), &widths, &mut output);
+            match result {
+                Err(OutputError::ValueTooWide {
+                    column,
+                    value,
+                    width,
+                }) => {
+                    assert_eq!(column, "available");
+                    assert_eq!(value, "1.5");
+                    assert_eq!(width, 2);
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    cell::Cell,
+    io::{self, Read, Write},
+    rc::Rc,
+};
+
+/// Number of transactions between reports when the input length is unknown.
+const REPORT_EVERY: u64 = 10_000;
+
+/// Wraps a reader, counting bytes consumed from it.
+pub struct ProgressReader<R> {
+    inner: R,
+    consumed: Rc<Cell<u64>>,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R) -> Self {
+        ProgressReader {
+            inner,
+            consumed: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Handle to the number of bytes consumed so far, usable after the reader
+    /// is moved into a CSV reader.
+    pub fn consumed(&self) -> Rc<Cell<u64>> {
+        Rc::clone(&self.consumed)
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.consumed.set(self.consumed.get() + read as u64);
+        Ok(read)
+    }
+}
+
+/// Reports processing progress as a percentage of consumed input, or as a
+/// transaction count when the input length is unknown (e.g. stdin).
+pub struct Progress<W> {
+    total_bytes: Option<u64>,
+    transactions: u64,
+    last_percent: Option<u64>,
+    out: W,
+}
+
+impl<W: Write> Progress<W> {
+    pub fn new(total_bytes: Option<u64>, out: W) -> Self {
+        Progress {
+            total_bytes,
+            transactions: 0,
+            last_percent: None,
+            out,
+        }
+    }
+
+    /// Records one processed transaction. Reading is buffered, so
+    /// `consumed_bytes` may run slightly ahead of processing.
+    pub fn update(&mut self, consumed_bytes: u64) -> io::Result<()> {
+        self.transactions += 1;
+        match self.total_bytes {
+            Some(total_bytes) => self.report_percent(percent(consumed_bytes, total_bytes)),
+            None if self.transactions % REPORT_EVERY == 0 => self.report_count(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.total_bytes {
+            Some(_) => self.report_percent(100),
+            None => self.report_count(),
+        }
+    }
+
+    fn report_percent(&mut self, percent: u64) -> io::Result<()> {
+        if self.last_percent == Some(percent) {
+            return Ok(());
+        }
+        self.last_percent = Some(percent);
+        writeln!(self.out, "progress: {}%", percent)
+    }
+
+    fn report_count(&mut self) -> io::Result<()> {
+        writeln!(self.out, "progress: {} transactions", self.transactions)
+    }
+}
+
+fn percent(consumed_bytes: u64, total_bytes: u64) -> u64 {
+    if total_bytes == 0 {
+        return 100;
+    }
+    (consumed_bytes.saturating_mul(100) / total_bytes).min(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_count_consumed_bytes() {
+        let mut reader = ProgressReader::new(&b"0123456789"[..]);
+        let consumed = reader.consumed();
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(consumed.get(), 4);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(consumed.get(), 10);
+    }
+
+    #[test]
+    fn should_report_percentage_up_to_100() {
+        let mut output = Vec::new();
+        let mut progress = Progress::new(Some(200), &mut output);
+        for consumed in &[50, 50, 120, 200] {
+            progress.update(*consumed).unwrap();
+        }
+        progress.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "progress: 25%\nprogress: 60%\nprogress: 100%\n"
+        );
+    }
+
+    #[test]
+    fn should_report_count_for_unknown_length() {
+        let mut output = Vec::new();
+        let mut progress = Progress::new(None, &mut output);
+        for _ in 0..REPORT_EVERY + 5 {
+            progress.update(0).unwrap();
+        }
+        progress.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "progress: {} transactions\nprogress: {} transactions\n",
+                REPORT_EVERY,
+                REPORT_EVERY + 5
+            )
+        );
+    }
+}
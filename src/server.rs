@@ -0,0 +1,132 @@
+use std::{net::ToSocketAddrs, sync::Mutex};
+
+use serde_json::json;
+use tiny_http::{Header, Response};
+
+use crate::{engine::Engine, ids::ClientId, input_types::parse_jsonl_transaction};
+
+/// Minimal HTTP front of a shared `Engine`:
+///
+/// - `POST /transactions` processes a transaction given as a JSON object, see
+///   `parse_jsonl_transaction`,
+/// - `GET /clients/{id}` returns the client's balances.
+pub struct Server {
+    engine: Mutex<Engine>,
+}
+
+impl Server {
+    pub fn new(engine: Engine) -> Self {
+        Server {
+            engine: Mutex::new(engine),
+        }
+    }
+
+    /// Serves requests on `addr` until the listener fails.
+    pub fn serve<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let server = tiny_http::Server::http(addr)?;
+        for mut request in server.incoming_requests() {
+            let mut body = String::new();
+            let (status, body) = match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => self.handle(request.method().as_str(), request.url(), &body),
+                Err(err) => error(400, err),
+            };
+            let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                .expect("static header is valid");
+            request.respond(
+                Response::from_string(body)
+                    .with_status_code(status)
+                    .with_header(content_type),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Handles a single request, returning the status code and JSON body.
+    pub fn handle(&self, method: &str, path: &str, body: &str) -> (u16, String) {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("POST", ["transactions"]) => self.post_transaction(body),
+            ("GET", ["clients", id]) => match id.parse() {
+                Ok(id) => self.get_client(id),
+                Err(err) => error(400, err),
+            },
+            _ => error(404, "not found"),
+        }
+    }
+
+    fn post_transaction(&self, body: &str) -> (u16, String) {
+        let transaction = match parse_jsonl_transaction(body) {
+            Ok(transaction) => transaction,
+            Err(err) => return error(400, err),
+        };
+        let mut engine = self.engine.lock().expect("engine lock poisoned");
+        match engine.process_transaction(transaction) {
+            Ok(()) => (200, json!({}).to_string()),
+            Err(err) => error(422, err),
+        }
+    }
+
+    fn get_client(&self, id: u16) -> (u16, String) {
+        let engine = self.engine.lock().expect("engine lock poisoned");
+        let client = match engine.client(ClientId(id)) {
+            Some(client) => client.snapshot(),
+            None => return error(404, "unknown client"),
+        };
+        let body = json!({
+            "client": id,
+            "available": client.available.to_string(),
+            "held": client.held.to_string(),
+            "total": client.total().to_string(),
+            "locked": client.is_frozen,
+        });
+        (200, body.to_string())
+    }
+}
+
+fn error<E: std::fmt::Display>(status: u16, err: E) -> (u16, String) {
+    (status, json!({ "error": err.to_string() }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_return_balance_after_deposit() {
+        let server = Server::new(Engine::new());
+        let (status, _) = server.handle(
+            "POST",
+            "/transactions",
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}"#,
+        );
+        assert_eq!(status, 200);
+
+        let (status, body) = server.handle("GET", "/clients/1", "");
+        assert_eq!(status, 200);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["available"], "1.5");
+        assert_eq!(body["total"], "1.5");
+        assert_eq!(body["locked"], false);
+    }
+
+    #[test]
+    fn should_reject_failed_transaction() {
+        let server = Server::new(Engine::new());
+        let (status, _) = server.handle(
+            "POST",
+            "/transactions",
+            r#"{"type": "withdrawal", "client": 1, "tx": 1, "amount": "1.5"}"#,
+        );
+        assert_eq!(status, 422);
+    }
+
+    #[test]
+    fn should_return_not_found_for_unknown_client() {
+        let server = Server::new(Engine::new());
+        assert_eq!(server.handle("GET", "/clients/7", "").0, 404);
+        assert_eq!(server.handle("GET", "/accounts/7", "").0, 404);
+    }
+}
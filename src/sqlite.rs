@@ -0,0 +1,160 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::{
+    client::{BalanceChangeEntryStatus, BalanceChangeEntryType},
+    engine::Engine,
+};
+
+impl Engine {
+    /// Writes final balances (`clients` table) and transaction history
+    /// (`transactions` table) into an SQLite database at `path`, creating the
+    /// tables if needed and replacing rows of a previous export. Amounts are
+    /// stored as text to keep full precision.
+    pub fn export_sqlite<P: AsRef<Path>>(&self, path: P) -> rusqlite::Result<()> {
+        let mut connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS clients (
+                client INTEGER PRIMARY KEY,
+                available TEXT NOT NULL,
+                held TEXT NOT NULL,
+                total TEXT NOT NULL,
+                locked INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS transactions (
+                tx INTEGER NOT NULL,
+                client INTEGER NOT NULL,
+                type TEXT NOT NULL,
+                amount TEXT NOT NULL,
+                status TEXT NOT NULL,
+                PRIMARY KEY (client, tx)
+            );",
+        )?;
+        let transaction = connection.transaction()?;
+        transaction.execute_batch("DELETE FROM clients; DELETE FROM transactions;")?;
+        {
+            let mut insert_client = transaction.prepare(
+                "INSERT INTO clients (client, available, held, total, locked)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut insert_transaction = transaction.prepare(
+                "INSERT INTO transactions (tx, client, type, amount, status)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (id, client) in self.iter_clients() {
+                insert_client.execute(params![
+                    id.0,
+                    client.available.to_string(),
+                    client.held.to_string(),
+                    client.total().to_string(),
+                    client.is_frozen,
+                ])?;
+                for (tx, entry) in client.history() {
+                    insert_transaction.execute(params![
+                        tx,
+                        id.0,
+                        entry_type_name(&entry.ty),
+                        entry.amount.to_string(),
+                        entry_status_name(&entry.status),
+                    ])?;
+                }
+            }
+        }
+        transaction.commit()
+    }
+}
+
+fn entry_type_name(ty: &BalanceChangeEntryType) -> &'static str {
+    match ty {
+        BalanceChangeEntryType::Deposit => "deposit",
+        BalanceChangeEntryType::Withdrawal => "withdrawal",
+    }
+}
+
+fn entry_status_name(status: &BalanceChangeEntryStatus) -> &'static str {
+    match status {
+        BalanceChangeEntryStatus::Pending => "pending",
+        BalanceChangeEntryStatus::Valid => "valid",
+        BalanceChangeEntryStatus::ActiveDispute => "active_dispute",
+        BalanceChangeEntryStatus::ChargedBack => "charged_back",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::input_types::{Transaction, TransactionType};
+
+    #[test]
+    fn should_export_clients_and_transactions() {
+        let path = std::env::temp_dir().join(format!(
+            "toy-payments-engine-export-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut engine = Engine::new();
+        engine
+            .process(vec![
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(15, 1))),
+                Transaction::new(TransactionType::Deposit, 2, 2, Some(Decimal::new(2, 0))),
+                Transaction::new(TransactionType::Dispute, 2, 2, None),
+            ])
+            .unwrap();
+
+        engine.export_sqlite(&path).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let available: String = connection
+            .query_row(
+                "SELECT available FROM clients WHERE client = ?1",
+                params![1],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(available, "1.5");
+        let status: String = connection
+            .query_row(
+                "SELECT status FROM transactions WHERE tx = ?1",
+                params![2],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(status, "active_dispute");
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_export_tx_ids_reused_by_several_clients_repeatedly() {
+        let path = std::env::temp_dir().join(format!(
+            "toy-payments-engine-export-reused-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut engine = Engine::new();
+        engine
+            .process(vec![
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(1, 0))),
+                Transaction::new(TransactionType::Deposit, 2, 1, Some(Decimal::new(2, 0))),
+            ])
+            .unwrap();
+
+        engine.export_sqlite(&path).unwrap();
+        engine.export_sqlite(&path).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let amounts: Vec<String> = connection
+            .prepare("SELECT amount FROM transactions WHERE tx = ?1 ORDER BY client")
+            .unwrap()
+            .query_map(params![1], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(amounts, vec!["1", "2"]);
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,2\n\
+                     deposit,2,2,3\n\
+                     withdrawal,1,3,1\n";
+
+const OUTPUT: &str = "client,available,held,total,locked\n\
+                      1,1,0,1,false\n\
+                      2,3,0,3,false\n";
+
+const EMPTY_OUTPUT: &str = "client,available,held,total,locked\n";
+
+fn run_with_stdin(args: &[&str], input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_toy-payments-engine"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn should_read_stdin_without_path() {
+    assert_eq!(run_with_stdin(&[], INPUT), OUTPUT);
+}
+
+#[test]
+fn should_read_stdin_for_dash() {
+    assert_eq!(run_with_stdin(&["-"], INPUT), OUTPUT);
+}
+
+#[test]
+fn should_write_only_header_for_empty_input() {
+    assert_eq!(run_with_stdin(&[], ""), EMPTY_OUTPUT);
+}
+
+#[test]
+fn should_write_only_header_for_header_only_input() {
+    assert_eq!(run_with_stdin(&[], "type,client,tx,amount\n"), EMPTY_OUTPUT);
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_data/input_empty.csv");
+    assert_eq!(run_with_stdin(&[path], ""), EMPTY_OUTPUT);
+}
+
+#[test]
+fn should_write_selected_columns() {
+    assert_eq!(
+        run_with_stdin(&["--columns=client,total,disputed_count"], INPUT),
+        "client,total,disputed_count\n\
+         1,1,0\n\
+         2,3,0\n"
+    );
+}